@@ -1,15 +1,70 @@
 use colored::*;
-use dialoguer::{theme::ColorfulTheme, Confirm};
+use dialoguer::{theme::ColorfulTheme, Confirm, Select};
 use difference::{Changeset, Difference};
 
 pub trait Cli {
     fn display_error(error: &str);
 
     fn confirm_update(filename: &str, original_contents: &str, updated_contents: &str) -> bool;
+
+    /// Pick one of the ways the compiler suggested fixing a problem. Each solution is a description
+    /// and the changes it makes, which can span several files.
+    fn select_solution(solutions: &[(String, Vec<FileChange>)], lint: Option<&str>) -> Choice;
+
+    /// Whether to go ahead and change files that have uncommitted changes.
+    fn confirm_dirty(files: &[String]) -> bool;
+
+    /// Whether to let the model read or change files that are usually best left alone.
+    fn confirm_sensitive(files: &[String]) -> bool;
+
+    /// Pick which of the models to ask for help, or `None` to not ask at all.
+    fn select_model(models: &[String]) -> Option<usize>;
+
+    /// Whether to roll back changes that introduced new errors.
+    fn confirm_revert(new_errors: &[String]) -> bool;
 }
 
+/// The contents of a file before and after a change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileChange {
+    pub filename: String,
+    pub original_contents: String,
+    pub updated_contents: String,
+}
+
+impl FileChange {
+    pub fn display_diff(&self) {
+        display_diff(
+            &self.filename,
+            &self.original_contents,
+            &self.updated_contents,
+        );
+    }
+}
+
+/// What to do about a problem the compiler knows how to fix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Choice {
+    /// Apply the solution at this index
+    Apply(usize),
+    /// Apply the first solution here and to every other problem from the same lint without asking
+    ApplyAll,
+    Skip,
+}
+
+/// Asks the user about everything.
 pub struct UserCli;
 
+/// Applies every update without asking.
+pub struct YesCli;
+
+/// Shows every update without applying any of them.
+pub struct DryRunCli;
+
+/// Shows every update without applying any of them, so the build can fail if anything could have
+/// been fixed.
+pub struct CiCli;
+
 impl Cli for UserCli {
     fn display_error(error: &str) {
         println!();
@@ -27,62 +82,288 @@ impl Cli for UserCli {
     }
 
     fn confirm_update(filename: &str, original_contents: &str, updated_contents: &str) -> bool {
-        let changeset = Changeset::new(original_contents, updated_contents, "\n");
+        display_diff(filename, original_contents, updated_contents);
 
-        let mut original_line_no = 1;
-        let mut updated_line_no = 1;
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Do you want to apply these changes?")
+            .default(true)
+            .interact()
+            .unwrap()
+    }
 
-        let mut last_change_line_no = 0;
+    fn select_solution(solutions: &[(String, Vec<FileChange>)], lint: Option<&str>) -> Choice {
+        let mut items = Vec::new();
 
-        for diff in &changeset.diffs {
-            let change = match diff {
-                Difference::Same(ref x) => {
-                    let lines = x.matches('\n').count() + 1;
-                    original_line_no += lines;
-                    updated_line_no += lines;
-                    None
-                }
-                Difference::Add(ref x) => {
-                    if !x.is_empty() {
-                        updated_line_no += 1;
-                        Some((updated_line_no - 1, format!("+{}", x).green()))
-                    } else {
-                        None
-                    }
-                }
-                Difference::Rem(ref x) => {
-                    original_line_no += 1;
-                    Some((original_line_no - 1, format!("-{}", x).red()))
-                }
-            };
-
-            if let Some((line, change)) = change {
-                let indent_size = vec![original_line_no, updated_line_no, line]
-                    .iter()
-                    .max()
-                    .unwrap()
-                    .to_string()
-                    .len();
-                let indent = " ".repeat(indent_size);
-
-                if original_line_no - last_change_line_no > 1 {
-                    println!(
-                        "{}{} {}:{}",
-                        indent,
-                        "-->".bright_blue().bold(),
-                        filename,
-                        original_line_no
-                    );
-                }
-                println!("{} {}", format!("{} |", line).bright_blue().bold(), change);
-                last_change_line_no = original_line_no;
+        for (index, (message, changes)) in solutions.iter().enumerate() {
+            if solutions.len() > 1 {
+                println!("{}", format!("[{}] {}", index + 1, message).bold());
+                items.push(format!("Apply [{}] {}", index + 1, message));
+            } else {
+                items.push("Apply".to_string());
             }
+            changes.iter().for_each(FileChange::display_diff);
         }
 
-        Confirm::with_theme(&ColorfulTheme::default())
+        if let Some(lint) = lint {
+            items.push(format!("Apply every `{}` fix", lint));
+        }
+        items.push("Skip".to_string());
+
+        let selection = Select::with_theme(&ColorfulTheme::default())
             .with_prompt("Do you want to apply these changes?")
+            .items(&items)
+            .default(0)
+            .interact()
+            .unwrap();
+
+        if selection < solutions.len() {
+            Choice::Apply(selection)
+        } else if lint.is_some() && selection == solutions.len() {
+            Choice::ApplyAll
+        } else {
+            Choice::Skip
+        }
+    }
+
+    fn confirm_dirty(files: &[String]) -> bool {
+        warn_dirty(files);
+
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Do you want to change them anyway?")
+            .default(true)
+            .interact()
+            .unwrap()
+    }
+
+    fn confirm_sensitive(files: &[String]) -> bool {
+        warn_sensitive(files);
+
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Do you want to let it?")
+            .default(false)
+            .interact()
+            .unwrap()
+    }
+
+    fn select_model(models: &[String]) -> Option<usize> {
+        println!();
+        let selection = Select::new()
+            .items(models)
+            .item("Nope")
+            .with_prompt(format!("{}", "Phone a friend? 📞🤖".bold().blue()))
+            .default(0)
+            .interact()
+            .unwrap();
+
+        (selection < models.len()).then_some(selection)
+    }
+
+    fn confirm_revert(new_errors: &[String]) -> bool {
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "The changes introduced {} new error(s), do you want to revert them?",
+                new_errors.len()
+            ))
             .default(true)
             .interact()
             .unwrap()
     }
 }
+
+impl Cli for YesCli {
+    fn display_error(error: &str) {
+        UserCli::display_error(error);
+    }
+
+    fn confirm_update(filename: &str, original_contents: &str, updated_contents: &str) -> bool {
+        display_diff(filename, original_contents, updated_contents);
+        println!("🤖 applying changes");
+        true
+    }
+
+    /// The compiler lists the solution it's most confident in first
+    fn select_solution(solutions: &[(String, Vec<FileChange>)], _lint: Option<&str>) -> Choice {
+        match solutions.first() {
+            Some((_, changes)) => {
+                changes.iter().for_each(FileChange::display_diff);
+                println!("🤖 applying changes");
+                Choice::Apply(0)
+            }
+            None => Choice::Skip,
+        }
+    }
+
+    fn confirm_dirty(files: &[String]) -> bool {
+        warn_dirty(files);
+        true
+    }
+
+    /// Nobody is around to ask, so leave them alone
+    fn confirm_sensitive(files: &[String]) -> bool {
+        warn_sensitive(files);
+        false
+    }
+
+    /// Models are listed in order of preference so go with the first one
+    fn select_model(models: &[String]) -> Option<usize> {
+        (!models.is_empty()).then_some(0)
+    }
+
+    /// Never keep changes that made things worse when nobody is watching
+    fn confirm_revert(_new_errors: &[String]) -> bool {
+        true
+    }
+}
+
+impl Cli for DryRunCli {
+    fn display_error(error: &str) {
+        UserCli::display_error(error);
+    }
+
+    fn confirm_update(filename: &str, original_contents: &str, updated_contents: &str) -> bool {
+        display_diff(filename, original_contents, updated_contents);
+        println!("🤖 dry run, not applying changes");
+        false
+    }
+
+    fn select_solution(solutions: &[(String, Vec<FileChange>)], _lint: Option<&str>) -> Choice {
+        for (message, changes) in solutions {
+            println!("{}", message.bold());
+            changes.iter().for_each(FileChange::display_diff);
+        }
+        println!("🤖 dry run, not applying changes");
+        Choice::Skip
+    }
+
+    /// Nothing gets written anyway
+    fn confirm_dirty(_files: &[String]) -> bool {
+        true
+    }
+
+    /// Reading them would still send them off to the model
+    fn confirm_sensitive(files: &[String]) -> bool {
+        YesCli::confirm_sensitive(files)
+    }
+
+    fn select_model(models: &[String]) -> Option<usize> {
+        YesCli::select_model(models)
+    }
+
+    fn confirm_revert(_new_errors: &[String]) -> bool {
+        true
+    }
+}
+
+impl Cli for CiCli {
+    fn display_error(error: &str) {
+        UserCli::display_error(error);
+    }
+
+    fn confirm_update(filename: &str, original_contents: &str, updated_contents: &str) -> bool {
+        display_diff(filename, original_contents, updated_contents);
+        false
+    }
+
+    fn select_solution(solutions: &[(String, Vec<FileChange>)], _lint: Option<&str>) -> Choice {
+        for change in solutions.iter().flat_map(|(_, changes)| changes) {
+            change.display_diff();
+        }
+        Choice::Skip
+    }
+
+    /// Nothing gets written anyway
+    fn confirm_dirty(_files: &[String]) -> bool {
+        true
+    }
+
+    fn confirm_sensitive(_files: &[String]) -> bool {
+        false
+    }
+
+    /// Don't spend tokens on every CI run, only report what the compiler can fix
+    fn select_model(_models: &[String]) -> Option<usize> {
+        None
+    }
+
+    fn confirm_revert(_new_errors: &[String]) -> bool {
+        true
+    }
+}
+
+fn warn_dirty(files: &[String]) {
+    println!();
+    println!(
+        "{}",
+        "These files have uncommitted changes:".yellow().bold()
+    );
+    for file in files {
+        println!("  {}", file);
+    }
+}
+
+fn warn_sensitive(files: &[String]) {
+    println!();
+    println!(
+        "{}",
+        "The robots want to look at protected files:"
+            .yellow()
+            .bold()
+    );
+    for file in files {
+        println!("  {}", file);
+    }
+}
+
+fn display_diff(filename: &str, original_contents: &str, updated_contents: &str) {
+    let changeset = Changeset::new(original_contents, updated_contents, "\n");
+
+    let mut original_line_no = 1;
+    let mut updated_line_no = 1;
+
+    let mut last_change_line_no = 0;
+
+    for diff in &changeset.diffs {
+        let change = match diff {
+            Difference::Same(ref x) => {
+                let lines = x.matches('\n').count() + 1;
+                original_line_no += lines;
+                updated_line_no += lines;
+                None
+            }
+            Difference::Add(ref x) => {
+                if !x.is_empty() {
+                    updated_line_no += 1;
+                    Some((updated_line_no - 1, format!("+{}", x).green()))
+                } else {
+                    None
+                }
+            }
+            Difference::Rem(ref x) => {
+                original_line_no += 1;
+                Some((original_line_no - 1, format!("-{}", x).red()))
+            }
+        };
+
+        if let Some((line, change)) = change {
+            let indent_size = [original_line_no, updated_line_no, line]
+                .iter()
+                .max()
+                .unwrap()
+                .to_string()
+                .len();
+            let indent = " ".repeat(indent_size);
+
+            if original_line_no - last_change_line_no > 1 {
+                println!(
+                    "{}{} {}:{}",
+                    indent,
+                    "-->".bright_blue().bold(),
+                    filename,
+                    original_line_no
+                );
+            }
+            println!("{} {}", format!("{} |", line).bright_blue().bold(), change);
+            last_change_line_no = original_line_no;
+        }
+    }
+}
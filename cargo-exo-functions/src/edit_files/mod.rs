@@ -0,0 +1,275 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, io,
+    path::Path,
+};
+
+use cargo_exo_cli::Cli;
+
+use crate::text_file::{ReadError, TextFile};
+
+pub use self::params::*;
+
+mod params;
+
+/// What a file will look like once the edits have been applied. The contents always use `\n`
+/// line endings like the model saw, they're put back the way they were when the file is written.
+pub struct PreparedEdit {
+    pub file: String,
+    pub causes: Vec<String>,
+    pub original: String,
+    pub updated: String,
+    text_file: TextFile,
+}
+
+/// Why a set of edits couldn't be applied, worded so it can be sent back to the model.
+#[derive(Debug, PartialEq)]
+pub struct Rejection(pub Vec<String>);
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the edits were rejected, nothing was changed:")?;
+        for problem in &self.0 {
+            write!(f, "\n- {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Works out what each file will look like after the edits without changing anything. Every
+/// edit has to match exactly one place in the file, otherwise the whole lot is rejected. Files
+/// that aren't text are left out.
+pub fn prepare(
+    params: &EditFilesParams,
+    project_root: &Path,
+) -> Result<Vec<PreparedEdit>, Rejection> {
+    let mut prepared = BTreeMap::<&str, PreparedEdit>::new();
+    let mut problems = vec![];
+    let mut skipped = BTreeSet::new();
+
+    for file_edit in &params.files {
+        if skipped.contains(file_edit.file.as_str()) {
+            continue;
+        }
+
+        if !prepared.contains_key(file_edit.file.as_str()) {
+            match TextFile::read(&project_root.join(&file_edit.file)) {
+                Ok(text_file) => {
+                    let contents = text_file.text();
+                    prepared.insert(
+                        &file_edit.file,
+                        PreparedEdit {
+                            file: file_edit.file.clone(),
+                            causes: vec![],
+                            original: contents.clone(),
+                            updated: contents,
+                            text_file,
+                        },
+                    );
+                }
+                Err(ReadError::NotUtf8) => {
+                    println!(
+                        "🤖 not changing {}, it {}",
+                        file_edit.file,
+                        ReadError::NotUtf8
+                    );
+                    skipped.insert(file_edit.file.as_str());
+                    continue;
+                }
+                Err(e) => {
+                    problems.push(format!("couldn't read {}: {}", file_edit.file, e));
+                    continue;
+                }
+            }
+        }
+
+        let edit = prepared.get_mut(file_edit.file.as_str()).unwrap();
+        edit.causes.push(file_edit.cause.clone());
+
+        for (index, change) in file_edit.edits.iter().enumerate() {
+            match apply(&edit.updated, change) {
+                Ok(updated) => edit.updated = updated,
+                Err(problem) => problems.push(format!(
+                    "edit {} in {} {}",
+                    index + 1,
+                    file_edit.file,
+                    problem
+                )),
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(prepared.into_values().collect())
+    } else {
+        Err(Rejection(problems))
+    }
+}
+
+/// Shows each file's changes and writes the ones the user accepts.
+pub fn edit_files<C: Cli>(prepared: &[PreparedEdit], project_root: &Path) -> io::Result<()> {
+    for edit in prepared {
+        if edit.updated == edit.original {
+            continue;
+        }
+
+        for cause in &edit.causes {
+            C::display_error(cause);
+        }
+
+        if C::confirm_update(&edit.file, &edit.original, &edit.updated) {
+            edit.text_file
+                .with_text(&edit.updated)
+                .write(&project_root.join(&edit.file))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn apply(contents: &str, edit: &Edit) -> Result<String, String> {
+    if edit.search.is_empty() {
+        return Err("has an empty search".to_string());
+    }
+
+    // In case the model copied the line endings from somewhere else
+    let search = edit.search.replace("\r\n", "\n");
+    let replace = edit.replace.replace("\r\n", "\n");
+
+    match contents.matches(&search).count() {
+        1 => Ok(contents.replacen(&search, &replace, 1)),
+        0 => Err(format!(
+            "doesn't match the file, search for text copied exactly from it:\n{}",
+            edit.search
+        )),
+        count => Err(format!(
+            "matches {} places, include more surrounding lines so it only matches one:\n{}",
+            count, edit.search
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use cargo_exo_cli::YesCli;
+
+    use crate::test_util::project;
+
+    use super::*;
+
+    fn edit(search: &str, replace: &str) -> Edit {
+        Edit {
+            search: search.to_string(),
+            replace: replace.to_string(),
+        }
+    }
+
+    #[test]
+    fn it_replaces_an_exact_match() {
+        let contents = "fn main() {\n    let x = 1;\n}\n";
+
+        assert_eq!(
+            apply(contents, &edit("    let x = 1;\n", "")).unwrap(),
+            "fn main() {\n}\n"
+        );
+    }
+
+    #[test]
+    fn it_rejects_missing_and_ambiguous_searches() {
+        let contents = "let x = 1;\nlet x = 1;\n";
+
+        assert!(apply(contents, &edit("let y = 1;", ""))
+            .unwrap_err()
+            .starts_with("doesn't match"));
+        assert!(apply(contents, &edit("let x = 1;", ""))
+            .unwrap_err()
+            .starts_with("matches 2 places"));
+        assert!(apply(contents, &edit("", "oops")).is_err());
+    }
+
+    #[test]
+    fn it_rejects_everything_if_one_edit_fails() {
+        let dir = project(&[("lib.rs", "fn a() {}\nfn b() {}\n")]);
+
+        let params = EditFilesParams {
+            files: vec![FileEdit {
+                cause: "error: oh no".to_string(),
+                file: "lib.rs".to_string(),
+                edits: vec![edit("fn a() {}", "fn c() {}"), edit("fn d() {}", "")],
+            }],
+        };
+
+        let rejection = prepare(&params, dir.path()).err().unwrap();
+
+        assert_eq!(rejection.0.len(), 1);
+        assert!(rejection.0[0].starts_with("edit 2 in lib.rs doesn't match"));
+    }
+
+    #[test]
+    fn it_applies_edits_in_order() {
+        let dir = project(&[("lib.rs", "fn a() {}\n")]);
+
+        let params = EditFilesParams {
+            files: vec![FileEdit {
+                cause: "error: oh no".to_string(),
+                file: "lib.rs".to_string(),
+                edits: vec![edit("fn a", "fn b"), edit("fn b() {}", "fn c() {}")],
+            }],
+        };
+
+        let prepared = prepare(&params, dir.path()).unwrap();
+
+        assert_eq!(prepared[0].updated, "fn c() {}\n");
+    }
+
+    #[test]
+    fn it_skips_files_that_arent_utf8() {
+        let dir = project(&[
+            ("data.bin", &[b'a', 0xff, b'\n'][..]),
+            ("lib.rs", b"fn a() {}\n"),
+        ]);
+
+        let params = EditFilesParams {
+            files: vec![
+                FileEdit {
+                    cause: "error: oh no".to_string(),
+                    file: "data.bin".to_string(),
+                    edits: vec![edit("a", "b")],
+                },
+                FileEdit {
+                    cause: "error: oh no".to_string(),
+                    file: "lib.rs".to_string(),
+                    edits: vec![edit("fn a", "fn b")],
+                },
+            ],
+        };
+
+        let prepared = prepare(&params, dir.path()).unwrap();
+
+        assert_eq!(prepared.len(), 1);
+        assert_eq!(prepared[0].file, "lib.rs");
+    }
+
+    #[test]
+    fn it_keeps_crlf_line_endings() {
+        let dir = project(&[("lib.rs", "fn a() {\r\n    1\r\n}\r\n")]);
+
+        let params = EditFilesParams {
+            files: vec![FileEdit {
+                cause: "error: oh no".to_string(),
+                file: "lib.rs".to_string(),
+                edits: vec![edit("fn a() {\n    1\n}", "fn a() {\n    2\n}")],
+            }],
+        };
+
+        let prepared = prepare(&params, dir.path()).unwrap();
+        edit_files::<YesCli>(&prepared, dir.path()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("lib.rs")).unwrap(),
+            "fn a() {\r\n    2\r\n}\r\n"
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct EditFilesParams {
+    pub files: Vec<FileEdit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct FileEdit {
+    /// The original error message that was returned from the compiler.
+    pub cause: String,
+    /// The file to be edited.
+    pub file: String,
+    /// The edits to make, in order.
+    pub edits: Vec<Edit>,
+}
+
+/// Replace one exact piece of the file with something else.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Edit {
+    /// The exact text to replace, copied from the file including indentation but without line
+    /// numbers. Include enough surrounding lines that it only appears once in the file.
+    pub search: String,
+    /// The text to put in its place, leave empty to delete it.
+    pub replace: String,
+}
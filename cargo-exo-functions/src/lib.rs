@@ -1,3 +1,8 @@
+pub mod edit_files;
 pub mod explain;
 pub mod more_context;
+pub mod text_file;
 pub mod update_files;
+
+#[cfg(test)]
+mod test_util;
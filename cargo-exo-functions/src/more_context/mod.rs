@@ -1,11 +1,134 @@
-use std::path::Path;
+use std::{fmt::Write, fs, path::Path};
 
 pub use self::params::*;
 
 mod params;
 
-pub fn more_context(params: &MoreContextParams, project_root: &Path) {
+/// Reads the requested files so they can be sent back to the model, with line numbers matching
+/// what `LineUpdate.line_no` expects.
+pub fn more_context(params: &MoreContextParams, project_root: &Path) -> String {
+    let mut context = String::new();
+
     for file in &params.files {
-        println!("{}", project_root.join(file).display());
+        let path = project_root.join(file);
+        println!("🤖 sending {}", path.display());
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                writeln!(context, "--> {}", file).unwrap();
+                context.push_str(&number_lines(&contents));
+            }
+            Err(e) => {
+                writeln!(context, "--> {}\ncould not read file: {}", file, e).unwrap();
+            }
+        }
+
+        context.push('\n');
+    }
+
+    context
+}
+
+fn number_lines(contents: &str) -> String {
+    let width = contents.lines().count().to_string().len();
+
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| format!("{:>width$} | {}\n", index + 1, line, width = width))
+        .collect()
+}
+
+/// Numbers just the lines in each range (1 based, inclusive) plus `padding` lines either side.
+/// Overlapping ranges are merged and the gaps between them are marked with `...`.
+pub fn number_line_ranges(contents: &str, ranges: &[(usize, usize)], padding: usize) -> String {
+    let lines = contents.lines().collect::<Vec<_>>();
+    let width = lines.len().to_string().len();
+
+    let mut ranges = ranges
+        .iter()
+        .map(|(start, end)| {
+            (
+                start.saturating_sub(padding).max(1),
+                (end + padding).min(lines.len()),
+            )
+        })
+        .filter(|(start, end)| start <= end)
+        .collect::<Vec<_>>();
+    ranges.sort();
+
+    let mut merged: Vec<(usize, usize)> = vec![];
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let mut numbered = String::new();
+    for (index, (start, end)) in merged.into_iter().enumerate() {
+        if index > 0 {
+            numbered.push_str("...\n");
+        }
+        for line_no in start..=end {
+            writeln!(
+                numbered,
+                "{:>width$} | {}",
+                line_no,
+                lines[line_no - 1],
+                width = width
+            )
+            .unwrap();
+        }
+    }
+
+    numbered
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_numbers_lines_from_one() {
+        let contents = (1..=10)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let numbered = number_lines(&contents);
+        let lines = numbered.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], " 1 | line 1");
+        assert_eq!(lines[9], "10 | line 10");
+    }
+
+    #[test]
+    fn it_merges_overlapping_line_ranges() {
+        let contents = (1..=20)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let numbered = number_line_ranges(&contents, &[(12, 12), (3, 3), (5, 5), (20, 20)], 1);
+        let lines = numbered.lines().collect::<Vec<_>>();
+
+        assert_eq!(
+            lines,
+            vec![
+                " 2 | line 2",
+                " 3 | line 3",
+                " 4 | line 4",
+                " 5 | line 5",
+                " 6 | line 6",
+                "...",
+                "11 | line 11",
+                "12 | line 12",
+                "13 | line 13",
+                "...",
+                "19 | line 19",
+                "20 | line 20",
+            ]
+        );
     }
 }
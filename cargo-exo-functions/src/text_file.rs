@@ -0,0 +1,194 @@
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::Path,
+};
+
+const BOM: char = '\u{feff}';
+
+/// The lines of a file along with how they were written, so the file can be written back the way
+/// we found it.
+#[derive(Debug, PartialEq)]
+pub struct TextFile {
+    pub lines: Vec<String>,
+    bom: bool,
+    crlf: bool,
+    final_newline: bool,
+}
+
+impl TextFile {
+    pub fn parse(contents: &str) -> Self {
+        let bom = contents.starts_with(BOM);
+        let contents = contents.trim_start_matches(BOM);
+
+        // Go with whatever most of the file uses
+        let crlf_count = contents.matches("\r\n").count();
+        let crlf = crlf_count > contents.matches('\n').count() - crlf_count;
+
+        let final_newline = contents.ends_with('\n');
+        let body = contents.strip_suffix('\n').unwrap_or(contents);
+
+        let lines = if contents.is_empty() {
+            vec![]
+        } else {
+            body.split('\n')
+                .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+                .collect()
+        };
+
+        Self {
+            lines,
+            bom,
+            crlf,
+            final_newline,
+        }
+    }
+
+    /// The contents with `\n` line endings and no BOM, the way the model sees them.
+    pub fn text(&self) -> String {
+        let mut text = self.lines.join("\n");
+        if self.final_newline && !self.lines.is_empty() {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// The same file with different contents, written with this one's line endings and BOM.
+    pub fn with_text(&self, text: &str) -> Self {
+        Self {
+            bom: self.bom,
+            crlf: self.crlf,
+            ..Self::parse(text)
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self, ReadError> {
+        let contents = String::from_utf8(fs::read(path)?).map_err(|_| ReadError::NotUtf8)?;
+        Ok(Self::parse(&contents))
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        write_atomic(path, &self.to_string())
+    }
+}
+
+/// Why a file couldn't be read as text.
+#[derive(Debug)]
+pub enum ReadError {
+    /// Binary or some other encoding, changing it line by line would mangle it
+    NotUtf8,
+    Io(io::Error),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::NotUtf8 => write!(f, "isn't UTF-8 text"),
+            ReadError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+impl fmt::Display for TextFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line_ending = if self.crlf { "\r\n" } else { "\n" };
+
+        if self.bom {
+            write!(f, "{}", BOM)?;
+        }
+        write!(f, "{}", self.lines.join(line_ending))?;
+        if self.final_newline && !self.lines.is_empty() {
+            write!(f, "{}", line_ending)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes to a temporary file next to `path` and renames it into place, so the file is never left
+/// half written if something goes wrong.
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let temp_path = path.with_file_name(format!(".{}.exo-tmp", file_name.to_string_lossy()));
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+
+        fs::rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::project;
+
+    use super::*;
+
+    #[test]
+    fn it_round_trips_line_endings_and_boms() {
+        for contents in ["a\nb\n", "a\nb", "a\r\nb\r\n", "\u{feff}a\r\nb", "", "\n"] {
+            assert_eq!(TextFile::parse(contents).to_string(), contents);
+        }
+    }
+
+    #[test]
+    fn it_strips_line_endings_from_lines() {
+        let file = TextFile::parse("\u{feff}a\r\nb\r\n");
+
+        assert_eq!(file.lines, vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn it_keeps_line_endings_when_the_text_changes() {
+        let file = TextFile::parse("\u{feff}a\r\nb\r\n");
+
+        assert_eq!(file.text(), "a\nb\n");
+        assert_eq!(
+            file.with_text("a\nc\nd\n").to_string(),
+            "\u{feff}a\r\nc\r\nd\r\n"
+        );
+    }
+
+    #[test]
+    fn it_refuses_to_read_files_that_arent_utf8() {
+        let dir = project(&[("logo.png", [0x89, b'P', b'N', b'G', 0xff, 0xfe])]);
+
+        assert!(matches!(
+            TextFile::read(&dir.path().join("logo.png")),
+            Err(ReadError::NotUtf8)
+        ));
+    }
+
+    #[test]
+    fn it_writes_atomically() {
+        let dir = project(&[("lib.rs", "old")]);
+        let path = dir.path().join("lib.rs");
+
+        write_atomic(&path, "new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!dir.path().join(".lib.rs.exo-tmp").exists());
+    }
+}
@@ -1,12 +1,10 @@
 pub use self::params::*;
-use cargo_exo_cli::{Cli, UserCli};
+use cargo_exo_cli::Cli;
 use itertools::Itertools;
 use rustfix::Suggestion;
-use std::{
-    fs::OpenOptions,
-    io::{BufRead, BufReader, Write},
-    path::Path,
-};
+use std::{io, path::Path};
+
+use crate::text_file::{ReadError, TextFile};
 
 mod params;
 
@@ -17,83 +15,143 @@ impl From<Suggestion> for LineUpdate {
 
         Self {
             line_no: snippet.line_range.start.line as i32,
+            end_line_no: None,
             action: LineAction::Replace,
             content: Some(replacement),
         }
     }
 }
 
-pub fn update_files(args: &UpdateFilesParams, project_root: &Path) {
+pub fn update_files<C: Cli>(args: &UpdateFilesParams, project_root: &Path) -> io::Result<()> {
     for file_update in &args.files {
         let path = project_root.join(&file_update.file);
-        let lines = {
-            let file = OpenOptions::new().read(true).open(&path).unwrap();
-            let reader = BufReader::new(file);
-            reader.lines().collect::<Result<_, _>>().unwrap()
+        let mut file = match TextFile::read(&path) {
+            Ok(file) => file,
+            Err(ReadError::NotUtf8) => {
+                println!(
+                    "🤖 not changing {}, it {}",
+                    file_update.file,
+                    ReadError::NotUtf8
+                );
+                continue;
+            }
+            Err(ReadError::Io(e)) => return Err(e),
         };
 
-        let updated_lines = update_lines::<UserCli>(file_update, lines);
-
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(path)
-            .unwrap();
+        let updated_lines = update_lines::<C>(file_update, file.lines.clone());
 
-        for line in &updated_lines {
-            writeln!(file, "{}", line).unwrap();
+        if updated_lines == file.lines {
+            continue;
         }
+
+        file.lines = updated_lines;
+        file.write(&path)?;
     }
+
+    Ok(())
 }
 
-fn update_lines<C: Cli>(file_update: &FileUpdate, mut lines: Vec<String>) -> Vec<String> {
+fn update_lines<C: Cli>(file_update: &FileUpdate, lines: Vec<String>) -> Vec<String> {
     C::display_error(&file_update.cause);
 
+    // Work from the bottom up so earlier line numbers stay put
     let rev_sorted_updates = file_update
         .lines
         .iter()
         .sorted_by(|a, b| b.line_no.cmp(&a.line_no));
 
+    let mut updated_lines = lines.clone();
+
     for line_update in rev_sorted_updates {
-        let mut updated_lines = lines.clone();
+        // Lines start at 1, anything else is a mistake and guessing could wipe out the file
+        if line_update.line_no < 1
+            || line_update
+                .end_line_no
+                .is_some_and(|end| end < line_update.line_no)
+        {
+            continue;
+        }
+
         let index = (line_update.line_no - 1) as usize;
+        let content = match line_update.content.as_deref() {
+            // Still a line, just a blank one
+            Some("") => vec![String::new()],
+            Some(content) => content.lines().map(String::from).collect(),
+            None => vec![],
+        };
+
+        // Ranges are inclusive, a missing end just means the one line
+        let end = line_update
+            .end_line_no
+            .map_or(index + 1, |end| end as usize);
+
+        // Inserting can add to the end of the file, everything else has to change lines it has
+        let past_the_end = match line_update.action {
+            LineAction::Insert => index > updated_lines.len(),
+            _ => end > updated_lines.len(),
+        };
+        if past_the_end {
+            println!(
+                "🤖 skipping a change to line {} of {}, it only has {} lines",
+                line_update.line_no,
+                file_update.file,
+                updated_lines.len()
+            );
+            continue;
+        }
 
         match line_update.action {
             LineAction::Insert => {
-                if let Some(ref content) = line_update.content {
-                    updated_lines.insert(index, content.clone());
-                }
+                updated_lines.splice(index..index, content);
             }
             LineAction::Replace => {
-                if let (Some(ref content), Some(line)) =
-                    (&line_update.content, updated_lines.get_mut(index))
-                {
-                    *line = content.clone();
+                if line_update.content.is_some() {
+                    updated_lines.splice(index..index + 1, content);
                 }
             }
             LineAction::Delete => {
                 updated_lines.remove(index);
             }
+            LineAction::ReplaceRange => {
+                updated_lines.splice(index..end, content);
+            }
+            LineAction::DeleteRange => {
+                updated_lines.drain(index..end);
+            }
         }
+    }
 
-        if C::confirm_update(
+    if updated_lines != lines
+        && C::confirm_update(
             &file_update.file,
             &lines.join("\n"),
             &updated_lines.join("\n"),
-        ) {
-            lines = updated_lines;
-        }
+        )
+    {
+        updated_lines
+    } else {
+        lines
     }
-
-    lines
 }
 
 #[cfg(test)]
 mod test {
+    use std::{cell::Cell, fs};
+
+    use cargo_exo_cli::{Choice, FileChange};
+
+    use crate::test_util::project;
+
     use super::*;
 
+    /// Accepts every update, counting how many it was asked about. Tests run on their own
+    /// threads so the count is per test.
     struct FakeCli;
 
+    thread_local! {
+        static ASKED: Cell<usize> = const { Cell::new(0) };
+    }
+
     impl Cli for FakeCli {
         fn display_error(_cause: &str) {}
 
@@ -102,8 +160,32 @@ mod test {
             _original_contents: &str,
             _updated_contents: &str,
         ) -> bool {
+            ASKED.with(|asked| asked.set(asked.get() + 1));
+            true
+        }
+
+        fn select_solution(
+            _solutions: &[(String, Vec<FileChange>)],
+            _lint: Option<&str>,
+        ) -> Choice {
+            Choice::Skip
+        }
+
+        fn confirm_dirty(_files: &[String]) -> bool {
             true
         }
+
+        fn confirm_sensitive(_files: &[String]) -> bool {
+            false
+        }
+
+        fn select_model(_models: &[String]) -> Option<usize> {
+            None
+        }
+
+        fn confirm_revert(_new_errors: &[String]) -> bool {
+            false
+        }
     }
 
     #[test]
@@ -115,6 +197,7 @@ mod test {
                 line_no: 1,
                 action: LineAction::Insert,
                 content: Some("Hello".to_string()),
+                end_line_no: None,
             }],
         };
         let lines = vec![
@@ -141,6 +224,7 @@ mod test {
                 line_no: 2,
                 action: LineAction::Replace,
                 content: Some("Hello".to_string()),
+                end_line_no: None,
             }],
         };
         let lines = vec![
@@ -157,6 +241,41 @@ mod test {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn it_blanks_lines_replaced_with_nothing() {
+        let file_update = FileUpdate {
+            file: "test.txt".to_string(),
+            cause: "Test".to_string(),
+            lines: vec![
+                LineUpdate {
+                    line_no: 2,
+                    action: LineAction::Replace,
+                    content: Some(String::new()),
+                    end_line_no: None,
+                },
+                LineUpdate {
+                    line_no: 3,
+                    action: LineAction::Insert,
+                    content: Some(String::new()),
+                    end_line_no: None,
+                },
+            ],
+        };
+        let lines = vec![
+            "Line 1".to_string(),
+            "Line 2".to_string(),
+            "Line 3".to_string(),
+        ];
+        let expected = vec![
+            "Line 1".to_string(),
+            String::new(),
+            String::new(),
+            "Line 3".to_string(),
+        ];
+        let result = update_lines::<FakeCli>(&file_update, lines);
+        assert_eq!(result, expected);
+    }
+
     #[test]
     fn it_deletes_lines() {
         let file_update = FileUpdate {
@@ -166,6 +285,7 @@ mod test {
                 line_no: 2,
                 action: LineAction::Delete,
                 content: None,
+                end_line_no: None,
             }],
         };
         let lines = vec![
@@ -187,16 +307,19 @@ mod test {
                     line_no: 1,
                     action: LineAction::Delete,
                     content: None,
+                    end_line_no: None,
                 },
                 LineUpdate {
                     line_no: 2,
                     action: LineAction::Replace,
                     content: Some("Hello".to_string()),
+                    end_line_no: None,
                 },
                 LineUpdate {
                     line_no: 3,
                     action: LineAction::Insert,
                     content: Some("World".to_string()),
+                    end_line_no: None,
                 },
             ],
         };
@@ -213,4 +336,137 @@ mod test {
         let result = update_lines::<FakeCli>(&file_update, lines);
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn it_replaces_and_deletes_ranges() {
+        let file_update = FileUpdate {
+            file: "test.txt".to_string(),
+            cause: "Test".to_string(),
+            lines: vec![
+                LineUpdate {
+                    line_no: 1,
+                    end_line_no: Some(2),
+                    action: LineAction::ReplaceRange,
+                    content: Some("Hello\nWorld\nAgain".to_string()),
+                },
+                LineUpdate {
+                    line_no: 4,
+                    end_line_no: Some(5),
+                    action: LineAction::DeleteRange,
+                    content: None,
+                },
+            ],
+        };
+        let lines = (1..=6).map(|i| format!("Line {}", i)).collect::<Vec<_>>();
+        let expected = vec![
+            "Hello".to_string(),
+            "World".to_string(),
+            "Again".to_string(),
+            "Line 3".to_string(),
+            "Line 6".to_string(),
+        ];
+        let result = update_lines::<FakeCli>(&file_update, lines);
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_skips_updates_to_lines_that_cant_exist() {
+        let update = |line_no, end_line_no, action| LineUpdate {
+            line_no,
+            end_line_no,
+            action,
+            content: Some("Hello".to_string()),
+        };
+        let file_update = FileUpdate {
+            file: "test.txt".to_string(),
+            cause: "Test".to_string(),
+            lines: vec![
+                update(-5, Some(-3), LineAction::DeleteRange),
+                update(0, Some(2), LineAction::DeleteRange),
+                update(3, Some(2), LineAction::DeleteRange),
+                update(2, Some(4), LineAction::ReplaceRange),
+                update(3, Some(9), LineAction::DeleteRange),
+                update(5, None, LineAction::Insert),
+                update(4, None, LineAction::Replace),
+                update(4, None, LineAction::Delete),
+            ],
+        };
+        let lines = vec![
+            "Line 1".to_string(),
+            "Line 2".to_string(),
+            "Line 3".to_string(),
+        ];
+
+        let result = update_lines::<FakeCli>(&file_update, lines.clone());
+
+        assert_eq!(result, lines);
+    }
+
+    #[test]
+    fn it_inserts_after_the_last_line() {
+        let file_update = FileUpdate {
+            file: "test.txt".to_string(),
+            cause: "Test".to_string(),
+            lines: vec![LineUpdate {
+                line_no: 3,
+                action: LineAction::Insert,
+                content: Some("Hello".to_string()),
+                end_line_no: None,
+            }],
+        };
+        let lines = vec!["Line 1".to_string(), "Line 2".to_string()];
+        let expected = vec![
+            "Line 1".to_string(),
+            "Line 2".to_string(),
+            "Hello".to_string(),
+        ];
+        let result = update_lines::<FakeCli>(&file_update, lines);
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_skips_files_that_arent_utf8() {
+        let bytes = [b'a', 0xff, b'\n', b'b', b'\n'];
+        let dir = project(&[("data.bin", bytes)]);
+
+        let params = UpdateFilesParams {
+            files: vec![FileUpdate {
+                file: "data.bin".to_string(),
+                cause: "Test".to_string(),
+                lines: vec![LineUpdate {
+                    line_no: 1,
+                    end_line_no: None,
+                    action: LineAction::Delete,
+                    content: None,
+                }],
+            }],
+        };
+
+        update_files::<FakeCli>(&params, dir.path()).unwrap();
+
+        assert_eq!(fs::read(dir.path().join("data.bin")).unwrap(), bytes);
+        assert_eq!(ASKED.with(Cell::get), 0);
+    }
+
+    #[test]
+    fn it_asks_once_per_file_update() {
+        let file_update = FileUpdate {
+            file: "test.txt".to_string(),
+            cause: "Test".to_string(),
+            lines: (1..=3)
+                .map(|line_no| LineUpdate {
+                    line_no,
+                    end_line_no: None,
+                    action: LineAction::Delete,
+                    content: None,
+                })
+                .collect(),
+        };
+        let lines = vec!["Line 1".to_string(), "Line 2".to_string()];
+
+        let result = update_lines::<FakeCli>(&file_update, lines);
+
+        assert!(result.is_empty());
+        assert_eq!(ASKED.with(Cell::get), 1);
+    }
 }
@@ -18,17 +18,21 @@ pub struct FileUpdate {
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct LineUpdate {
-    /// The line number to be updated
+    /// The line number to be updated, or the first line of the range for range actions
     pub line_no: i32,
+    /// The last line of the range (inclusive) for `replace_range` and `delete_range`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_line_no: Option<i32>,
     /// The content of the line to be updated. Attempt to preserve white space if replacing.
     /// The white space ios everything between the | and the start of the line of code.
+    /// Can span multiple lines.
     pub content: Option<String>,
     /// The action to be taken on the line
     pub action: LineAction,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
 pub enum LineAction {
     /// Replace the contents of the line
     Replace,
@@ -37,4 +41,8 @@ pub enum LineAction {
     Insert,
     /// Delete the line
     Delete,
+    /// Replace every line from `line_no` to `end_line_no` with the content
+    ReplaceRange,
+    /// Delete every line from `line_no` to `end_line_no`
+    DeleteRange,
 }
@@ -1,3 +1,4 @@
+use cargo_exo_functions::edit_files::EditFilesParams;
 use cargo_exo_functions::explain::ExplainParams;
 use cargo_exo_functions::more_context::MoreContextParams;
 use cargo_exo_functions::update_files::UpdateFilesParams;
@@ -13,6 +14,10 @@ fn main() {
     schema_settings.inline_subschemas = true;
 
     let schemars = vec![
+        (
+            "edit_files_schema.json",
+            SchemaGenerator::new(schema_settings.clone()).into_root_schema_for::<EditFilesParams>(),
+        ),
         (
             "update_files_schema.json",
             SchemaGenerator::new(schema_settings.clone())
@@ -0,0 +1,235 @@
+use serde_json::{json, Value};
+
+use crate::error::ExoError;
+use crate::model::request::Request;
+use crate::model::response::Response;
+
+use super::Provider;
+
+pub const URL: &str = "https://api.anthropic.com/v1";
+
+const VERSION: &str = "2023-06-01";
+
+/// Anthropic style messages APIs. Function calls are sent as tools and the response is translated
+/// back into the OpenAI shape.
+pub struct Anthropic {
+    base_url: String,
+    api_key: String,
+}
+
+impl Anthropic {
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+}
+
+impl Provider for Anthropic {
+    fn default_models(&self) -> Vec<String> {
+        vec![
+            "claude-3-5-sonnet-latest".to_string(),
+            "claude-3-5-haiku-latest".to_string(),
+        ]
+    }
+
+    async fn send_request(
+        &self,
+        client: &reqwest::Client,
+        request: &Request,
+    ) -> Result<Response, ExoError> {
+        let res = client
+            .post(format!("{}/messages", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", VERSION)
+            .json(&to_messages_request(request))
+            .send()
+            .await?;
+
+        let body = super::read_body(res).await?;
+
+        super::save_debug_output(body.clone());
+
+        let value = serde_json::from_str::<Value>(&body).map_err(ExoError::MalformedJson)?;
+
+        from_messages_response(value)
+    }
+}
+
+fn to_messages_request(request: &Request) -> Value {
+    let request = serde_json::to_value(request).unwrap();
+
+    let mut system = Vec::new();
+    let mut messages = Vec::new();
+    let mut tool_use_id = String::new();
+
+    for (index, message) in request["messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        let content = message["content"].clone();
+
+        match message["role"].as_str() {
+            Some("system") => system.push(content.as_str().unwrap_or_default().to_string()),
+            Some("assistant") if message["function_call"].is_object() => {
+                let function_call = &message["function_call"];
+                let arguments = function_call["arguments"].as_str().unwrap_or("{}");
+                tool_use_id = format!("toolu_{}", index);
+
+                messages.push(json!({
+                    "role": "assistant",
+                    "content": [{
+                        "type": "tool_use",
+                        "id": tool_use_id,
+                        "name": function_call["name"],
+                        "input": serde_json::from_str::<Value>(arguments).unwrap_or_default(),
+                    }],
+                }));
+            }
+            Some("function") => messages.push(json!({
+                "role": "user",
+                "content": [{
+                    "type": "tool_result",
+                    "tool_use_id": tool_use_id,
+                    "content": content,
+                }],
+            })),
+            role => messages.push(json!({ "role": role, "content": content })),
+        }
+    }
+
+    let tools = request["functions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|function| {
+            json!({
+                "name": function["name"],
+                "description": function["description"],
+                "input_schema": function["parameters"],
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "model": request["model"],
+        "temperature": request["temperature"],
+        "max_tokens": request["max_tokens"],
+        "system": system.join("\n\n"),
+        "messages": messages,
+        "tools": tools,
+    })
+}
+
+fn from_messages_response(value: Value) -> Result<Response, ExoError> {
+    let content = value["content"].as_array().cloned().unwrap_or_default();
+
+    let text = content
+        .iter()
+        .filter(|block| block["type"] == "text")
+        .filter_map(|block| block["text"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // Arguments come back as an object but the OpenAI shape has them as a JSON string
+    let function_call = content
+        .iter()
+        .find(|block| block["type"] == "tool_use")
+        .map(|block| json!({ "name": block["name"], "arguments": block["input"].to_string() }));
+
+    let prompt_tokens = value["usage"]["input_tokens"].as_i64().unwrap_or_default();
+    let completion_tokens = value["usage"]["output_tokens"].as_i64().unwrap_or_default();
+
+    super::parse_response(json!({
+        "id": value["id"],
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": value["model"],
+        "choices": [{
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": if text.is_empty() { None } else { Some(text) },
+                "function_call": function_call,
+            },
+            "finish_reason": value["stop_reason"],
+        }],
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use cargo_exo_functions::more_context::MoreContextParams;
+
+    use crate::model::response::FunctionCall;
+
+    use super::*;
+
+    #[test]
+    fn it_converts_function_calls_to_tools() {
+        let mut request = Request::new(
+            "clippy".to_string(),
+            "error: oh no".to_string(),
+            String::new(),
+            "claude".to_string(),
+        );
+        request.max_tokens = 1024;
+        let params = MoreContextParams {
+            files: vec!["src/main.rs".to_string()],
+        };
+        request.add_more_context(&params, "1 | fn main() {}".to_string());
+
+        let body = to_messages_request(&request);
+        let messages = body["messages"].as_array().unwrap();
+
+        assert_eq!(body["max_tokens"], 1024);
+        assert!(!body["system"].as_str().unwrap().is_empty());
+        assert_eq!(body["tools"].as_array().unwrap().len(), 4);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(
+            messages[1]["content"][0]["input"]["files"][0],
+            "src/main.rs"
+        );
+        assert_eq!(
+            messages[2]["content"][0]["tool_use_id"],
+            messages[1]["content"][0]["id"]
+        );
+    }
+
+    #[test]
+    fn it_converts_tool_use_to_a_function_call() {
+        let response = from_messages_response(json!({
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude",
+            "content": [
+                { "type": "text", "text": "I need to see the file" },
+                {
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "more_context",
+                    "input": { "files": ["src/main.rs"] },
+                },
+            ],
+            "stop_reason": "tool_use",
+            "usage": { "input_tokens": 100, "output_tokens": 20 },
+        }))
+        .unwrap();
+
+        assert_eq!(response.usage.total_tokens, 120);
+        assert!(matches!(
+            &response.choices[0].message.function_call,
+            Some(FunctionCall::MoreContext(params)) if params.files == ["src/main.rs"]
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::config::{Config, ProviderConfig};
+use crate::error::ExoError;
+use crate::model::error::ErrorResponse;
+use crate::model::request::Request;
+use crate::model::response::Response;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+pub use self::anthropic::{Anthropic, URL as ANTHROPIC_URL};
+pub use self::openai::OpenAi;
+
+mod anthropic;
+mod openai;
+
+const DEBUG: bool = true;
+
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// Don't wait any longer than this between retries, if the API asks us to then we give up instead
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// A chat completion API that can answer our requests.
+///
+/// Requests and responses are always in the OpenAI shape, providers with a different API are
+/// responsible for translating to and from it.
+pub trait Provider {
+    /// The models to offer when no models have been configured.
+    fn default_models(&self) -> Vec<String>;
+
+    async fn send_request(
+        &self,
+        client: &reqwest::Client,
+        request: &Request,
+    ) -> Result<Response, ExoError>;
+}
+
+/// Sends the request to the configured provider, retrying rate limits, server errors and network
+/// failures. `on_retry` is called with the delay and attempt number before each retry.
+pub async fn send_request(
+    request: &Request,
+    config: &Config,
+    mut on_retry: impl FnMut(Duration, usize),
+) -> Result<Response, ExoError> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.request_timeout))
+        .build()?;
+
+    let mut attempt = 0;
+
+    loop {
+        let result = match &config.provider {
+            ProviderConfig::OpenAi => {
+                OpenAi::new(openai::URL, &config.api_key)
+                    .send_request(&client, request)
+                    .await
+            }
+            ProviderConfig::OpenAiCompatible { base_url } => {
+                OpenAi::new(base_url, &config.api_key)
+                    .send_request(&client, request)
+                    .await
+            }
+            ProviderConfig::Anthropic { base_url } => {
+                Anthropic::new(base_url, &config.api_key)
+                    .send_request(&client, request)
+                    .await
+            }
+        };
+
+        let error = match result {
+            Err(error) if error.is_retryable() && attempt < config.max_retries => error,
+            result => return result,
+        };
+
+        attempt += 1;
+
+        let delay = match error.retry_after() {
+            Some(delay) if delay > MAX_RETRY_DELAY => return Err(error),
+            Some(delay) => delay,
+            None => backoff(attempt),
+        };
+
+        on_retry(delay, attempt);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// The models the user can pick from, either from the config or the provider's defaults.
+pub fn models(config: &Config) -> Vec<String> {
+    if !config.models.is_empty() {
+        return config.models.clone();
+    }
+
+    match &config.provider {
+        ProviderConfig::OpenAi => OpenAi::new(openai::URL, &config.api_key).default_models(),
+        ProviderConfig::OpenAiCompatible { base_url } => {
+            OpenAi::new(base_url, &config.api_key).default_models()
+        }
+        ProviderConfig::Anthropic { base_url } => {
+            Anthropic::new(base_url, &config.api_key).default_models()
+        }
+    }
+}
+
+/// Exponential backoff with jitter so we don't retry in lockstep with anything else.
+fn backoff(attempt: usize) -> Duration {
+    let delay = BASE_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt as u32 - 1))
+        .min(MAX_RETRY_DELAY);
+
+    // Good enough randomness without pulling in another crate
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = (random % 1000) as f64 / 1000.0;
+
+    delay.mul_f64(0.5 + jitter / 2.0)
+}
+
+/// How long the API has asked us to wait, from either `retry-after` or the `x-ratelimit-reset-*`
+/// header of whichever limit has run out. The others can be a long way off, e.g. a daily limit,
+/// without being what stopped us.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(retry_after) = header("retry-after") {
+        if let Ok(seconds) = retry_after.trim().parse::<f64>() {
+            return Some(Duration::from_secs_f64(seconds.max(0.0)));
+        }
+
+        if let Ok(date) = chrono::DateTime::parse_from_rfc2822(retry_after) {
+            let delay = date.signed_duration_since(chrono::Utc::now());
+            return Some(delay.to_std().unwrap_or_default());
+        }
+    }
+
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let limit = name.as_str().strip_prefix("x-ratelimit-reset-")?;
+            let remaining = header(&format!("x-ratelimit-remaining-{}", limit))?;
+            if remaining.trim() != "0" {
+                return None;
+            }
+            parse_reset_duration(value.to_str().ok()?)
+        })
+        .max()
+}
+
+/// Parses durations like `1s`, `6m0s`, `20ms` or `1.5s` from OpenAI's rate limit headers.
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = Duration::ZERO;
+    let mut rest = value.trim();
+
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (number, remainder) = rest.split_at(number_len);
+        let number = number.parse::<f64>().ok()?;
+
+        let unit_len = remainder
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(remainder.len());
+        let (unit, remainder) = remainder.split_at(unit_len);
+
+        let seconds = match unit {
+            "ms" => number / 1000.0,
+            "s" | "" => number,
+            "m" => number * 60.0,
+            "h" => number * 60.0 * 60.0,
+            _ => return None,
+        };
+
+        total += Duration::from_secs_f64(seconds);
+        rest = remainder;
+    }
+
+    Some(total)
+}
+
+/// Reads the body of a response, turning any error status into an `ExoError`.
+async fn read_body(res: reqwest::Response) -> Result<String, ExoError> {
+    let status = res.status();
+    let retry_after = retry_after(res.headers());
+    let body = res.text().await?;
+
+    if status.is_success() {
+        return Ok(body);
+    }
+
+    // Not every server sends an error in the shape we expect, fall back to the raw body
+    let message = serde_json::from_str::<ErrorResponse>(&body)
+        .map(|response| response.error.message)
+        .unwrap_or(body);
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        Err(ExoError::RateLimit {
+            message,
+            retry_after,
+        })
+    } else {
+        Err(ExoError::Http {
+            status,
+            message,
+            retry_after,
+        })
+    }
+}
+
+fn parse_response(value: Value) -> Result<Response, ExoError> {
+    let has_function_call = value["choices"][0]["message"]["function_call"].is_object();
+
+    serde_json::from_value(value).map_err(|e| {
+        if has_function_call {
+            ExoError::InvalidFunctionArguments(e)
+        } else {
+            ExoError::MalformedJson(e)
+        }
+    })
+}
+
+fn save_debug_output(text: String) {
+    if !DEBUG {
+        return;
+    }
+
+    let timestamp = chrono::Local::now().format("%Y%m%d%H%M%S");
+    let path = "/tmp/cargo_exo_debug";
+    let filepath = format!("{}/{}.json", path, timestamp);
+
+    println!("🤖 saving debug output to {}", filepath);
+
+    // It's only for debugging, not worth losing a response we've paid for
+    if let Err(e) = std::fs::create_dir_all(path).and_then(|_| std::fs::write(&filepath, text)) {
+        eprintln!("🤖 couldn't save debug output: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn it_separates_bad_function_arguments_from_bad_responses() {
+        let response = |function_call: Value| {
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1688744543,
+                "model": "gpt-4-0613",
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": null, "function_call": function_call },
+                    "finish_reason": "function_call"
+                }],
+                "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 }
+            })
+        };
+
+        let bad_arguments = response(json!({ "name": "explain", "arguments": "{\"nope\": 1}" }));
+        assert!(matches!(
+            parse_response(bad_arguments),
+            Err(ExoError::InvalidFunctionArguments(_))
+        ));
+
+        assert!(matches!(
+            parse_response(json!({ "id": "chatcmpl-1" })),
+            Err(ExoError::MalformedJson(_))
+        ));
+    }
+
+    #[test]
+    fn it_parses_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-reset-requests", "1s".parse().unwrap());
+        headers.insert("x-ratelimit-reset-tokens", "6m0s".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert("x-ratelimit-remaining-requests", "0".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-tokens", "1500".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(1)));
+
+        headers.insert("x-ratelimit-remaining-requests", "3".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-tokens", "0".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(360)));
+
+        headers.insert("retry-after", "20".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(20)));
+
+        assert_eq!(
+            parse_reset_duration("1.5s"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            parse_reset_duration("20ms"),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(parse_reset_duration("soon"), None);
+    }
+
+    #[test]
+    fn it_backs_off_exponentially() {
+        assert!(backoff(1) <= Duration::from_secs(1));
+        assert!(backoff(3) >= Duration::from_secs(2));
+        assert!(backoff(3) <= Duration::from_secs(4));
+        assert!(backoff(100) <= MAX_RETRY_DELAY);
+    }
+}
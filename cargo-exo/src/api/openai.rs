@@ -0,0 +1,64 @@
+use crate::error::ExoError;
+use crate::model::request::Request;
+use crate::model::response::Response;
+use serde_json::Value;
+
+use super::Provider;
+
+pub const URL: &str = "https://api.openai.com/v1";
+
+pub const GPT_3_5: &str = "gpt-3.5-turbo-0613";
+pub const GPT_4: &str = "gpt-4-0613";
+
+/// OpenAI, or anything else that speaks its chat completions API (llama.cpp server, vLLM, Ollama).
+pub struct OpenAi {
+    base_url: String,
+    api_key: String,
+}
+
+impl OpenAi {
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+}
+
+impl Provider for OpenAi {
+    fn default_models(&self) -> Vec<String> {
+        if self.base_url == URL {
+            vec![GPT_3_5.to_string(), GPT_4.to_string()]
+        } else {
+            // There's no way to guess what a self-hosted server is running
+            vec![]
+        }
+    }
+
+    async fn send_request(
+        &self,
+        client: &reqwest::Client,
+        request: &Request,
+    ) -> Result<Response, ExoError> {
+        let mut builder = client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(request);
+
+        // Local servers usually don't need a key at all
+        if !self.api_key.is_empty() {
+            builder = builder.bearer_auth(&self.api_key);
+        }
+
+        let body = super::read_body(builder.send().await?).await?;
+        let value = serde_json::from_str::<Value>(&body).map_err(ExoError::MalformedJson)?;
+        let response = super::parse_response(value);
+
+        super::save_debug_output(if let Ok(ref response) = response {
+            serde_json::to_string_pretty(response).unwrap()
+        } else {
+            body
+        });
+
+        response
+    }
+}
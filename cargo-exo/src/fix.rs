@@ -1,64 +1,428 @@
-use crate::cargo::CargoCommandResult;
+use crate::diagnostic::Diagnostic;
+use crate::error::ExoError;
 use crate::Path;
-use cargo_exo_cli::{Cli, UserCli};
+use cargo_exo_cli::{Choice, Cli, FileChange};
+use cargo_exo_functions::text_file::write_atomic;
 use colored::Colorize;
-use rustfix::{Filter, Suggestion};
-use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use itertools::Itertools;
+use rustfix::{Filter, Solution, Suggestion};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 
-pub fn get_suggestions(cmd_result: &CargoCommandResult) -> Vec<Suggestion> {
-    let messages = cmd_result
-        .stdout
-        .split('\n')
-        .filter_map(|s| serde_json::from_str::<Value>(s).ok())
-        .filter_map(|value| value.get("message").cloned());
-
-    messages
-        .filter_map(|message| {
-            let msg_str = message.to_string();
-            // TODO - can we update this to just parse the Value directly?
-            rustfix::get_suggestions_from_json(&msg_str, &HashSet::new(), Filter::Everything).ok()
+/// A suggestion from the compiler along with the lint that raised it, if there was one.
+pub struct Fix {
+    pub lint: Option<String>,
+    pub suggestion: Suggestion,
+}
+
+pub fn get_suggestions(diagnostics: &[Diagnostic]) -> Vec<Fix> {
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| {
+            // rustfix keeps its own copy of the diagnostic types with private fields, so hand it JSON
+            let json = serde_json::to_string(diagnostic).ok()?;
+            let suggestions =
+                rustfix::get_suggestions_from_json(&json, &HashSet::new(), Filter::Everything)
+                    .ok()?;
+            let lint = diagnostic.code.as_ref().map(|code| code.code.clone());
+
+            Some(suggestions.into_iter().map(move |suggestion| Fix {
+                lint: lint.clone(),
+                suggestion,
+            }))
         })
         .flatten()
         .collect::<Vec<_>>()
 }
 
-pub fn update_files(suggestions: Vec<Suggestion>, project_root: &Path) {
-    let mut files = HashMap::new();
-    for suggestion in suggestions {
-        let file = suggestion.solutions[0].replacements[0]
-            .snippet
-            .file_name
-            .clone();
-        files.entry(file).or_insert_with(Vec::new).push(suggestion);
+/// The files the suggestions would change.
+pub fn files(fixes: &[Fix]) -> Vec<String> {
+    fixes
+        .iter()
+        .flat_map(|fix| &fix.suggestion.solutions)
+        .flat_map(|solution| &solution.replacements)
+        .map(|replacement| replacement.snippet.file_name.clone())
+        .collect()
+}
+
+/// Asks about each fix and writes the ones that are accepted, returns the files with fixes that
+/// were skipped.
+pub fn update_files<C: Cli>(
+    fixes: Vec<Fix>,
+    auto_apply: &[String],
+    project_root: &Path,
+) -> Result<Vec<String>, ExoError> {
+    let mut pending = Pending::default();
+    let mut skipped = Vec::<String>::new();
+
+    // Lints the user has said to fix everywhere
+    let mut apply_all = auto_apply
+        .iter()
+        .map(String::as_str)
+        .collect::<HashSet<_>>();
+
+    for fix in &fixes {
+        let solutions = alternatives(&fix.suggestion)
+            .into_iter()
+            .filter_map(|solution| {
+                match pending.preview(&fix.suggestion, &solution, project_root) {
+                    Ok(parts) => Some((solution.message, parts)),
+                    Err(e) => {
+                        eprintln!("Failed to apply suggestion: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if solutions.is_empty() {
+            continue;
+        }
+
+        let lint = fix.lint.as_deref();
+
+        println!();
+        println!("{}", fix.suggestion.message.bold());
+
+        let choice = match lint {
+            Some(lint) if apply_all.contains(lint) => {
+                println!("🤖 applying every `{}` fix", lint);
+                Choice::Apply(0)
+            }
+            _ => {
+                let changes = solutions
+                    .iter()
+                    .map(|(message, parts)| {
+                        let changes = parts.iter().map(|(_, change)| change.clone()).collect();
+                        (message.clone(), changes)
+                    })
+                    .collect::<Vec<_>>();
+
+                C::select_solution(&changes, lint)
+            }
+        };
+
+        let selected = match choice {
+            Choice::Apply(index) => index,
+            Choice::ApplyAll => {
+                apply_all.extend(lint);
+                0
+            }
+            Choice::Skip => {
+                for (_, change) in solutions.iter().flat_map(|(_, parts)| parts) {
+                    if !skipped.contains(&change.filename) {
+                        skipped.push(change.filename.clone());
+                    }
+                }
+                continue;
+            }
+        };
+
+        // Every file in the solution gets its part, so it's never half applied
+        for (suggestion, change) in &solutions[selected].1 {
+            pending.accept(&change.filename, suggestion.clone());
+        }
     }
 
-    for (file, suggestions) in &files {
-        let filepath = project_root.join(file);
-        let mut source = fs::read_to_string(&filepath).unwrap_or_else(|_| panic!("{:?}", filepath));
-        let mut change_counter = 0;
+    pending.write(project_root)?;
+
+    Ok(skipped)
+}
+
+/// The original source of every file we've looked at and the fixes accepted for each. Fixes are
+/// always applied on top of the original source because that's what the compiler's byte offsets
+/// point at.
+#[derive(Default)]
+struct Pending {
+    files: BTreeMap<String, (String, Vec<Suggestion>)>,
+}
+
+impl Pending {
+    /// What each file would look like with the solution applied on top of everything accepted so
+    /// far, along with the part of the suggestion that applies to it.
+    fn preview(
+        &mut self,
+        suggestion: &Suggestion,
+        solution: &Solution,
+        project_root: &Path,
+    ) -> Result<Vec<(Suggestion, FileChange)>, String> {
+        let replacements = solution
+            .replacements
+            .iter()
+            .into_group_map_by(|replacement| replacement.snippet.file_name.clone());
+
+        replacements
+            .into_iter()
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(file, replacements)| {
+                let part = Suggestion {
+                    message: suggestion.message.clone(),
+                    snippets: suggestion
+                        .snippets
+                        .iter()
+                        .filter(|snippet| snippet.file_name == file)
+                        .cloned()
+                        .collect(),
+                    solutions: vec![Solution {
+                        message: solution.message.clone(),
+                        replacements: replacements.into_iter().cloned().collect(),
+                    }],
+                };
+
+                let (original, accepted) = self.file(&file, project_root)?;
+                let original_contents = apply(original, accepted)?;
+                let updated_contents = apply(original, accepted.iter().chain([&part]))
+                    .map_err(|e| format!("{}: {}", file, e))?;
+
+                Ok((
+                    part,
+                    FileChange {
+                        filename: file,
+                        original_contents,
+                        updated_contents,
+                    },
+                ))
+            })
+            .collect()
+    }
 
-        for suggestion in suggestions.iter().rev() {
-            let mut fix = rustfix::CodeFix::new(&source);
+    fn accept(&mut self, file: &str, suggestion: Suggestion) {
+        if let Some((_, accepted)) = self.files.get_mut(file) {
+            accepted.push(suggestion);
+        }
+    }
 
-            if let Err(e) = fix.apply(suggestion) {
-                eprintln!("Failed to apply suggestion to {}: {}", file, e);
+    fn write(self, project_root: &Path) -> Result<(), ExoError> {
+        for (file, (original, accepted)) in &self.files {
+            if accepted.is_empty() {
+                continue;
             }
 
-            let fixes = fix.finish().unwrap();
+            // Already checked when previewing so this can't fail
+            let source = apply(original, accepted).unwrap_or_else(|_| original.clone());
+
+            println!("🤖 writing {} changes to {}", accepted.len(), file);
+            write_atomic(&project_root.join(file), &source)?;
+        }
+
+        Ok(())
+    }
+
+    fn file(
+        &mut self,
+        file: &str,
+        project_root: &Path,
+    ) -> Result<&(String, Vec<Suggestion>), String> {
+        if !self.files.contains_key(file) {
+            let source = fs::read_to_string(project_root.join(file))
+                .map_err(|e| format!("{}: {}", file, e))?;
+            self.files.insert(file.to_string(), (source, vec![]));
+        }
+
+        Ok(&self.files[file])
+    }
+}
+
+/// Each separate way of fixing the suggestion. The compiler lists candidates like "consider
+/// importing one of these items" as replacements for the same span, those are split up so only
+/// one of them gets applied.
+fn alternatives(suggestion: &Suggestion) -> Vec<Solution> {
+    suggestion
+        .solutions
+        .iter()
+        .flat_map(|solution| {
+            let same_span = solution
+                .replacements
+                .iter()
+                .map(|replacement| &replacement.snippet.range)
+                .all_equal();
 
-            println!();
-            println!("{}", suggestion.message.bold());
-            if UserCli::confirm_update(file, &source, &fixes) {
-                source = fixes;
-                change_counter += 1;
+            if same_span && solution.replacements.len() > 1 {
+                solution
+                    .replacements
+                    .iter()
+                    .map(|replacement| Solution {
+                        message: format!(
+                            "{} `{}`",
+                            solution.message,
+                            replacement.replacement.trim()
+                        ),
+                        replacements: vec![replacement.clone()],
+                    })
+                    .collect()
+            } else {
+                vec![solution.clone()]
             }
+        })
+        .collect()
+}
+
+fn apply<'a>(
+    source: &str,
+    suggestions: impl IntoIterator<Item = &'a Suggestion>,
+) -> Result<String, String> {
+    let mut fix = rustfix::CodeFix::new(source);
+
+    for suggestion in suggestions {
+        fix.apply(suggestion).map_err(|e| e.to_string())?;
+    }
+
+    fix.finish().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use rustfix::{LinePosition, LineRange, Replacement, Snippet};
+
+    use crate::test_util::project;
+
+    use super::*;
+
+    fn replacement(file: &str, range: std::ops::Range<usize>, replacement: &str) -> Replacement {
+        let position = LinePosition { line: 1, column: 1 };
+
+        Replacement {
+            snippet: Snippet {
+                file_name: file.to_string(),
+                line_range: LineRange {
+                    start: position,
+                    end: position,
+                },
+                range,
+                text: Default::default(),
+            },
+            replacement: replacement.to_string(),
+        }
+    }
+
+    fn suggestion(message: &str, replacements: Vec<Replacement>) -> Suggestion {
+        Suggestion {
+            message: message.to_string(),
+            snippets: vec![],
+            solutions: vec![Solution {
+                message: message.to_string(),
+                replacements,
+            }],
+        }
+    }
+
+    #[test]
+    fn it_splits_candidates_for_the_same_span() {
+        let suggestion = suggestion(
+            "consider importing one of these items",
+            vec![
+                replacement("src/main.rs", 9..9, "use rand::Distribution;\n"),
+                replacement("src/main.rs", 9..9, "use statrs::Distribution;\n"),
+            ],
+        );
+
+        let alternatives = alternatives(&suggestion);
+        let part = Suggestion {
+            solutions: vec![alternatives[1].clone()],
+            ..suggestion
+        };
+
+        assert_eq!(alternatives.len(), 2);
+        assert_eq!(
+            apply("//! docs\nfn main() {}\n", [&part]).unwrap(),
+            "//! docs\nuse statrs::Distribution;\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_multi_part_solutions_together() {
+        let suggestion = suggestion(
+            "remove the braces",
+            vec![
+                replacement("src/main.rs", 0..1, ""),
+                replacement("src/main.rs", 4..5, ""),
+            ],
+        );
+
+        assert_eq!(alternatives(&suggestion).len(), 1);
+        assert_eq!(apply("(abc)", [&suggestion]).unwrap(), "abc");
+    }
+
+    #[test]
+    fn it_applies_each_file_its_own_replacements() {
+        let dir = project(&[("src/main.rs", "let x = 1;"), ("src/lib.rs", "fn f() {}")]);
+        let project_root = dir.path();
+        let suggestion = suggestion(
+            "rename everywhere",
+            vec![
+                replacement("src/main.rs", 4..5, "y"),
+                replacement("src/lib.rs", 3..4, "g"),
+            ],
+        );
+
+        let mut pending = Pending::default();
+        let parts = pending
+            .preview(&suggestion, &suggestion.solutions[0], project_root)
+            .unwrap();
+
+        assert_eq!(
+            parts
+                .iter()
+                .map(|(_, change)| (change.filename.as_str(), change.updated_contents.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("src/lib.rs", "fn g() {}"), ("src/main.rs", "let y = 1;")]
+        );
+
+        for (part, change) in parts {
+            pending.accept(&change.filename, part);
         }
+        pending.write(project_root).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(project_root.join("src/main.rs")).unwrap(),
+            "let y = 1;"
+        );
+        assert_eq!(
+            fs::read_to_string(project_root.join("src/lib.rs")).unwrap(),
+            "fn g() {}"
+        );
+    }
 
-        if change_counter > 0 {
-            println!("🤖 writing {} changes to {}", change_counter, file);
-            fs::write(filepath, source).unwrap();
+    #[test]
+    fn it_applies_fixes_in_any_order() {
+        let dir = project(&[("src/main.rs", "let a = 1; let b = 2;")]);
+        let project_root = dir.path();
+        let first = suggestion("first", vec![replacement("src/main.rs", 4..5, "_a")]);
+        let second = suggestion("second", vec![replacement("src/main.rs", 15..16, "_b")]);
+
+        let mut pending = Pending::default();
+        for suggestion in [&first, &second] {
+            let parts = pending
+                .preview(suggestion, &suggestion.solutions[0], project_root)
+                .unwrap();
+            for (part, change) in parts {
+                pending.accept(&change.filename, part);
+            }
         }
+        pending.write(project_root).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(project_root.join("src/main.rs")).unwrap(),
+            "let _a = 1; let _b = 2;"
+        );
+    }
+
+    #[test]
+    fn it_rejects_solutions_for_files_it_cant_read() {
+        let dir = project(&[("src/main.rs", "let x = 1;")]);
+        let project_root = dir.path();
+        let suggestion = suggestion(
+            "rename everywhere",
+            vec![
+                replacement("src/main.rs", 4..5, "y"),
+                replacement("src/missing.rs", 0..1, "z"),
+            ],
+        );
+
+        let mut pending = Pending::default();
+
+        assert!(pending
+            .preview(&suggestion, &suggestion.solutions[0], project_root)
+            .is_err());
     }
 }
@@ -0,0 +1,166 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    config::{BeforeFixing, GitConfig},
+    error::ExoError,
+    summary::Engine,
+};
+use cargo_exo_cli::Cli;
+
+/// Keeps our changes from getting tangled up with the user's. Does nothing outside of a git
+/// repository, or when we're not going to write anything.
+pub struct Git<'a> {
+    root: Option<PathBuf>,
+    config: &'a GitConfig,
+    prepared: bool,
+}
+
+impl<'a> Git<'a> {
+    pub fn new(project_root: &Path, config: &'a GitConfig, writes_files: bool) -> Self {
+        let root = writes_files
+            .then(|| git(project_root, &["rev-parse", "--show-toplevel"]).ok())
+            .flatten()
+            .map(|root| PathBuf::from(root.trim()));
+
+        Self {
+            root,
+            config,
+            prepared: false,
+        }
+    }
+
+    /// Called before changing `files` (relative to the project root). Returns `false` if the user
+    /// would rather we left them alone.
+    pub fn before_changes<C: Cli>(
+        &mut self,
+        files: &[String],
+        project_root: &Path,
+    ) -> Result<bool, ExoError> {
+        let Some(root) = self.root.clone() else {
+            return Ok(true);
+        };
+
+        if !self.prepared {
+            self.prepared = true;
+
+            match self.config.before_fixing {
+                BeforeFixing::Nothing => {}
+                BeforeFixing::WipCommit => {
+                    // Only files git already tracks, untracked ones could be anything, e.g. a
+                    // `.env` that isn't ignored
+                    let status = git(&root, &["status", "--porcelain", "--untracked-files=no"])?;
+                    if !status.trim().is_empty() {
+                        println!("🤖 committing your changes before fixing anything");
+                        git(&root, &["add", "--update"])?;
+                        git(&root, &["commit", "--message", "WIP before cargo exo"])?;
+                    }
+                }
+                BeforeFixing::Branch => {
+                    let branch = chrono::Local::now().format("exo/%Y%m%d-%H%M%S").to_string();
+                    println!("🤖 switching to {}", branch);
+                    git(&root, &["switch", "--create", &branch])?;
+                }
+            }
+        }
+
+        let mut args = vec!["status", "--porcelain", "--"];
+        args.extend(files.iter().map(String::as_str));
+
+        let dirty = git(project_root, &args)?
+            .lines()
+            .filter_map(|line| line.get(3..))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+
+        Ok(dirty.is_empty() || C::confirm_dirty(&dirty))
+    }
+
+    /// Commits the files a fix changed, with a message describing what it fixed.
+    pub fn commit_fix(
+        &self,
+        engine: Engine,
+        diagnostics: &[String],
+        files: &[PathBuf],
+    ) -> Result<(), ExoError> {
+        let Some(root) = &self.root else {
+            return Ok(());
+        };
+
+        if !self.config.commit_fixes || files.is_empty() {
+            return Ok(());
+        }
+
+        let files = files
+            .iter()
+            .filter_map(|file| file.to_str())
+            .collect::<Vec<_>>();
+
+        // Added first so new files are known to git, then only these files are committed so
+        // anything the user has staged stays out of it
+        let mut add = vec!["add", "--"];
+        add.extend(&files);
+        git(root, &add)?;
+
+        let message = commit_message(engine, diagnostics);
+        let mut commit = vec!["commit", "--only", "--message", &message, "--"];
+        commit.extend(&files);
+        git(root, &commit)?;
+
+        Ok(())
+    }
+}
+
+fn commit_message(engine: Engine, diagnostics: &[String]) -> String {
+    let subject = match diagnostics {
+        [diagnostic] => format!("Fix {}", diagnostic),
+        _ => format!("Fix {} diagnostics", diagnostics.len()),
+    };
+
+    let mut message = format!("{}\n\nFixed by {} with cargo exo:\n", subject, engine);
+    for diagnostic in diagnostics {
+        message.push_str(&format!("\n- {}", diagnostic));
+    }
+
+    message
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, ExoError> {
+    let output = Command::new("git").args(args).current_dir(dir).output()?;
+
+    if !output.status.success() {
+        return Err(ExoError::Git(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_describes_what_was_fixed() {
+        let one = commit_message(
+            Engine::Rustfix,
+            &["warning[unused_imports]: unused import: `std::fs`".to_string()],
+        );
+        assert!(one.starts_with("Fix warning[unused_imports]: unused import: `std::fs`\n\n"));
+
+        let many = commit_message(
+            Engine::Robots,
+            &[
+                "error[E0425]: cannot find value `y` in this scope".to_string(),
+                "error[E0308]: mismatched types".to_string(),
+            ],
+        );
+        assert!(many.starts_with("Fix 2 diagnostics\n\nFixed by the robots"));
+        assert!(many.ends_with("- error[E0308]: mismatched types"));
+    }
+}
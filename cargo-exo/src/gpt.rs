@@ -1,55 +1,280 @@
-use std::{path::Path, time::Duration};
+use std::{collections::BTreeMap, fmt::Write, fs, path::Path, time::Duration};
 
-use colored::Colorize;
-use dialoguer::Select;
+use cargo_exo_cli::Cli;
+use cargo_exo_functions::{edit_files::Rejection, more_context::MoreContextParams};
 use indicatif::ProgressBar;
+use itertools::Itertools;
 use tokio::select;
 
 use crate::{
     api,
     cargo::CargoCommandResult,
     config::Config,
+    diagnostic::{Diagnostic, Level},
+    error::ExoError,
+    git::Git,
     model::{
-        request::{Request, GPT_3_5, GPT_4},
-        response::FunctionCall,
+        request::Request,
+        response::{FunctionCall, Response},
     },
+    sandbox::Sandbox,
+    verify::Snapshot,
 };
 
-pub async fn ask_the_robots(
+/// What the robots changed, who did it and how many tokens it took.
+#[derive(Default)]
+pub struct Answer {
+    pub snapshot: Snapshot,
+    pub model: Option<String>,
+    pub tokens_used: i32,
+}
+
+pub async fn ask_the_robots<C: Cli>(
     cmd: &str,
     cmd_result: &CargoCommandResult,
+    diagnostics: &[Diagnostic],
     config: &Config,
+    git: &mut Git<'_>,
+    sandbox: &Sandbox,
     project_root: &Path,
-) {
-    // Remove everything from output before the first "error: " line
-    let output = cmd_result
-        .stderr
-        .split('\n')
-        .skip_while(|line| !line.starts_with("error: "))
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    println!();
-    let model = match Select::new()
-        .items(&["GPT 3.5 Turbo", "GPT 4", "Nope"])
-        .with_prompt(format!("{}", "Phone a friend? 📞🤖".bold().blue()))
-        .default(0)
-        .interact()
-        .unwrap()
-    {
-        0 => GPT_3_5,
-        1 => GPT_4,
-        _ => return,
+) -> Result<Answer, ExoError> {
+    let mut output = compiler_output(diagnostics);
+
+    // Not every failure comes from the compiler, e.g. a broken manifest
+    if output.is_empty() {
+        output = cmd_result.stderr.trim().to_string();
+    }
+
+    let models = api::models(config);
+
+    if models.is_empty() {
+        println!("🤖 no models configured!");
+        return Ok(Answer::default());
+    }
+
+    let Some(model) = C::select_model(&models).map(|selection| &models[selection]) else {
+        return Ok(Answer::default());
     };
 
-    let request = Request::new(cmd.to_string(), output, model.to_string());
-    let mut request_fut = Box::pin(api::send_request(&request, config.api_key.clone()));
+    let context = source_context::<C>(diagnostics, sandbox, project_root, config.context_lines);
+    let mut request = Request::new(cmd.to_string(), output, context, model.to_string());
+    request.temperature = config.temperature as f32;
+    request.max_tokens = config.max_tokens;
+    let mut tokens_used = 0;
+    let mut snapshot = Snapshot::default();
+
+    for turn in 1..=config.max_turns {
+        let result = send_request(&request, config, model).await?;
+
+        tokens_used += result.usage.total_tokens;
+
+        match &result.message()?.function_call {
+            Some(FunctionCall::EditFiles(params)) => {
+                let files = params
+                    .files
+                    .iter()
+                    .map(|file_edit| file_edit.file.clone())
+                    .collect::<Vec<_>>();
+                let (_, refused) = sandbox.filter::<C>(&files);
+
+                let prepared = if refused.is_empty() {
+                    cargo_exo_functions::edit_files::prepare(params, project_root)
+                } else {
+                    Err(Rejection(
+                        refused
+                            .iter()
+                            .map(|(file, reason)| format!("{} {}", file, reason))
+                            .collect(),
+                    ))
+                };
+
+                match prepared {
+                    Ok(prepared) => {
+                        let files = prepared
+                            .iter()
+                            .map(|edit| edit.file.clone())
+                            .collect::<Vec<_>>();
+
+                        if git.before_changes::<C>(&files, project_root)? {
+                            snapshot = Snapshot::take(files, project_root);
+                            cargo_exo_functions::edit_files::edit_files::<C>(
+                                &prepared,
+                                project_root,
+                            )?;
+                        }
+                    }
+                    Err(rejection) => {
+                        println!("🤖 {}", rejection);
+
+                        // Let the model have another go with the reason it didn't work
+                        if turn == config.max_turns {
+                            println!("🤖 giving up after {} turns!", turn);
+                        } else if tokens_used >= config.token_budget {
+                            println!("🤖 giving up after using {} tokens!", tokens_used);
+                        } else {
+                            request.add_function_result(
+                                stringify!(edit_files),
+                                params,
+                                rejection.to_string(),
+                            );
+                            continue;
+                        }
+                    }
+                }
+            }
+            Some(FunctionCall::UpdateFile(params)) => {
+                let files = params
+                    .files
+                    .iter()
+                    .map(|file_update| file_update.file.clone())
+                    .collect::<Vec<_>>();
+                let (_, refused) = sandbox.filter::<C>(&files);
+
+                for (file, reason) in &refused {
+                    println!("🤖 not changing anything, {} {}", file, reason);
+                }
+
+                if refused.is_empty() && git.before_changes::<C>(&files, project_root)? {
+                    snapshot = Snapshot::take(files, project_root);
+                    cargo_exo_functions::update_files::update_files::<C>(params, project_root)?;
+                }
+            }
+            Some(FunctionCall::Explain(params)) => {
+                cargo_exo_functions::explain::explain(params);
+            }
+            Some(FunctionCall::MoreContext(params)) => {
+                if turn == config.max_turns {
+                    println!("🤖 giving up after {} turns!", turn);
+                } else if tokens_used >= config.token_budget {
+                    println!("🤖 giving up after using {} tokens!", tokens_used);
+                } else {
+                    let (files, refused) = sandbox.filter::<C>(&params.files);
+
+                    let mut context = cargo_exo_functions::more_context::more_context(
+                        &MoreContextParams { files },
+                        project_root,
+                    );
+                    for (file, reason) in refused {
+                        println!("🤖 not sending {}, it {}", file, reason);
+                        writeln!(context, "--> {}\nnot allowed, it {}\n", file, reason).unwrap();
+                    }
+
+                    request.add_more_context(params, context);
+                    continue;
+                }
+            }
+            None => {
+                println!("🤖 no changes to make!");
+            }
+        }
+
+        break;
+    }
+
+    Ok(Answer {
+        snapshot,
+        model: Some(model.to_string()),
+        tokens_used,
+    })
+}
+
+/// The rendered errors and warnings, as the compiler would have printed them. Cargo repeats
+/// diagnostics for every target that hits them so duplicates are dropped.
+fn compiler_output(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .filter(|diagnostic| matches!(diagnostic.level, Level::Error | Level::Warning | Level::Ice))
+        .filter(|diagnostic| !diagnostic.is_summary())
+        .filter_map(|diagnostic| diagnostic.rendered.as_deref())
+        .unique()
+        .map(str::trim_end)
+        .join("\n\n")
+}
+
+/// The source around the primary span of each error and warning, grouped by file so each file is
+/// only sent once and only if the sandbox allows it. Line numbers match what `LineUpdate.line_no`
+/// expects.
+fn source_context<C: Cli>(
+    diagnostics: &[Diagnostic],
+    sandbox: &Sandbox,
+    project_root: &Path,
+    context_lines: usize,
+) -> String {
+    if context_lines == 0 {
+        return String::new();
+    }
+
+    let mut files = BTreeMap::<&str, Vec<(usize, usize)>>::new();
+    for diagnostic in diagnostics
+        .iter()
+        .filter(|diagnostic| matches!(diagnostic.level, Level::Error | Level::Warning | Level::Ice))
+    {
+        for span in diagnostic.primary_spans() {
+            files
+                .entry(&span.file_name)
+                .or_default()
+                .push((span.line_start, span.line_end));
+        }
+    }
+
+    // Spans can point into the standard library or other crates, we can't change those anyway
+    files.retain(|file, _| !Path::new(file).is_absolute());
+
+    let names = files
+        .keys()
+        .map(|file| file.to_string())
+        .collect::<Vec<_>>();
+    let (allowed, refused) = sandbox.filter::<C>(&names);
+    for (file, reason) in refused {
+        println!("🤖 not sending {}, it {}", file, reason);
+    }
+
+    let mut context = String::new();
+
+    for (file, ranges) in files {
+        if !allowed.iter().any(|allowed| allowed == file) {
+            continue;
+        }
+
+        let Ok(contents) = fs::read_to_string(project_root.join(file)) else {
+            continue;
+        };
+
+        writeln!(context, "--> {}", file).unwrap();
+        context.push_str(&cargo_exo_functions::more_context::number_line_ranges(
+            &contents,
+            &ranges,
+            context_lines,
+        ));
+        context.push('\n');
+    }
 
+    context.trim_end().to_string()
+}
+
+async fn send_request(
+    request: &Request,
+    config: &Config,
+    model: &str,
+) -> Result<Response, ExoError> {
     let spinner = ProgressBar::new_spinner();
     spinner.set_message(format!("🤖 thinking ... ({})", model));
+
+    let retry_spinner = spinner.clone();
+    let max_retries = config.max_retries;
+    let mut request_fut = Box::pin(api::send_request(request, config, |delay, attempt| {
+        retry_spinner.set_message(format!(
+            "🤖 retrying in {}s (attempt {}/{}) ... ({})",
+            delay.as_secs_f32().ceil(),
+            attempt,
+            max_retries,
+            model
+        ));
+    }));
+
     let mut interval = tokio::time::interval(Duration::from_millis(50));
 
-    let result = loop {
+    loop {
         select! {
             result = &mut request_fut => {
                 spinner.finish_with_message("🤖 done!");
@@ -57,28 +282,105 @@ pub async fn ask_the_robots(
             },
             _ = interval.tick() => spinner.tick(),
         }
-    };
+    }
+}
 
-    let result = match result {
-        Ok(result) => result,
-        Err(e) => {
-            println!("🤖 {:?}", e);
-            return;
-        }
-    };
+#[cfg(test)]
+mod test {
+    use crate::test_util::project;
 
-    match &result.choices[0].message.function_call {
-        Some(FunctionCall::UpdateFile(params)) => {
-            cargo_exo_functions::update_files::update_files(params, project_root);
-        }
-        Some(FunctionCall::Explain(params)) => {
-            cargo_exo_functions::explain::explain(params);
-        }
-        Some(FunctionCall::MoreContext(params)) => {
-            cargo_exo_functions::more_context::more_context(params, project_root)
-        }
-        None => {
-            println!("🤖 no changes to make!");
+    use super::*;
+
+    fn diagnostic(level: Level, rendered: &str) -> Diagnostic {
+        Diagnostic {
+            message: rendered.to_string(),
+            code: None,
+            level,
+            spans: vec![crate::diagnostic::Span {
+                file_name: "src/main.rs".to_string(),
+                byte_start: 0,
+                byte_end: 1,
+                line_start: 1,
+                line_end: 1,
+                column_start: 1,
+                column_end: 2,
+                is_primary: true,
+                text: vec![],
+                label: None,
+                suggested_replacement: None,
+                suggestion_applicability: None,
+                expansion: None,
+            }],
+            children: vec![],
+            rendered: Some(format!("{}\n", rendered)),
         }
     }
+
+    #[test]
+    fn it_only_sends_errors_and_warnings_once() {
+        let mut summary = diagnostic(Level::Error, "error: aborting due to previous error");
+        summary.message = "aborting due to previous error".to_string();
+        summary.spans.clear();
+
+        let diagnostics = vec![
+            diagnostic(Level::Warning, "warning: unused variable: `x`"),
+            diagnostic(Level::Error, "error[E0425]: cannot find value `y`"),
+            diagnostic(Level::Warning, "warning: unused variable: `x`"),
+            diagnostic(Level::Note, "note: something else"),
+            summary,
+        ];
+
+        assert_eq!(
+            compiler_output(&diagnostics),
+            "warning: unused variable: `x`\n\nerror[E0425]: cannot find value `y`"
+        );
+    }
+
+    #[test]
+    fn it_sends_each_file_once() {
+        let contents = (1..=30)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let dir = project(&[
+            ("src/main.rs", contents.as_str()),
+            ("src/secrets.rs", "password"),
+        ]);
+        let project_root = dir.path();
+
+        let mut first = diagnostic(Level::Error, "error: one");
+        first.spans[0].line_start = 3;
+        first.spans[0].line_end = 3;
+        let mut second = diagnostic(Level::Warning, "warning: two");
+        second.spans[0].line_start = 5;
+        second.spans[0].line_end = 5;
+        let mut outside = diagnostic(Level::Error, "error: three");
+        outside.spans[0].file_name = "/rustc/library/core/src/lib.rs".to_string();
+        let mut denied = diagnostic(Level::Error, "error: four");
+        denied.spans[0].file_name = "src/secrets.rs".to_string();
+        let mut escaped = diagnostic(Level::Error, "error: five");
+        escaped.spans[0].file_name = "../secret.txt".to_string();
+
+        let sandbox = Sandbox::with_members(
+            project_root,
+            vec![project_root.to_path_buf()],
+            None,
+            &crate::config::SandboxConfig {
+                deny: vec!["secrets.rs".to_string()],
+                ..Default::default()
+            },
+        );
+
+        let context = source_context::<cargo_exo_cli::YesCli>(
+            &[first, second, outside, denied, escaped],
+            &sandbox,
+            project_root,
+            2,
+        );
+
+        assert_eq!(
+            context,
+            "--> src/main.rs\n 1 | line 1\n 2 | line 2\n 3 | line 3\n 4 | line 4\n 5 | line 5\n 6 | line 6\n 7 | line 7"
+        );
+    }
 }
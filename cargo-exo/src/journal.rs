@@ -0,0 +1,305 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use cargo_exo_cli::FileChange;
+use chrono::{DateTime, Local};
+use colored::Colorize;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ExoError, summary::Engine, verify::Snapshot};
+
+/// Everything we changed during one run, kept under `target/exo/` so it can be listed and undone.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub started: DateTime<Local>,
+    pub command: String,
+    #[serde(default)]
+    pub undone: bool,
+    pub entries: Vec<Entry>,
+}
+
+/// A change to one file that we kept after verifying it.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: DateTime<Local>,
+    pub engine: Engine,
+    pub model: Option<String>,
+    /// The diagnostics that went away with this change
+    pub diagnostics: Vec<String>,
+    pub file: String,
+    pub before: String,
+    pub after: String,
+}
+
+impl Session {
+    pub fn new(command: &str) -> Self {
+        let started = Local::now();
+
+        Self {
+            id: started.format("%Y%m%d-%H%M%S").to_string(),
+            started,
+            command: command.to_string(),
+            undone: false,
+            entries: vec![],
+        }
+    }
+
+    /// Records whatever is still different from the snapshot, i.e. the changes we kept.
+    pub fn record(
+        &mut self,
+        engine: Engine,
+        model: Option<&str>,
+        diagnostics: &[String],
+        snapshot: &Snapshot,
+        project_root: &Path,
+    ) {
+        for (path, before, after) in snapshot.changes() {
+            // Only source files get changed, anything else isn't worth keeping
+            let (Ok(before), Ok(after)) = (String::from_utf8(before), String::from_utf8(after))
+            else {
+                continue;
+            };
+
+            let file = path.strip_prefix(project_root).unwrap_or(&path);
+
+            self.entries.push(Entry {
+                timestamp: Local::now(),
+                engine,
+                model: model.map(str::to_string),
+                diagnostics: diagnostics.to_vec(),
+                file: file.display().to_string(),
+                before,
+                after,
+            });
+        }
+    }
+
+    pub fn save(&mut self, project_root: &Path) -> Result<(), ExoError> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+
+        // `cargo fmt` runs after our changes, so what's on disk now is what undo should expect
+        let mut seen = Vec::new();
+        for entry in self.entries.iter_mut().rev() {
+            if !seen.contains(&entry.file) {
+                if let Ok(contents) = fs::read_to_string(project_root.join(&entry.file)) {
+                    entry.after = contents;
+                }
+                seen.push(entry.file.clone());
+            }
+        }
+
+        let dir = journal_dir(project_root);
+        fs::create_dir_all(&dir)?;
+
+        // Watch mode can start more than one session a second
+        let id = self.id.clone();
+        let mut suffix = 1;
+        while dir.join(format!("{}.json", self.id)).exists() {
+            suffix += 1;
+            self.id = format!("{}-{}", id, suffix);
+        }
+
+        self.write(project_root)
+    }
+
+    fn write(&self, project_root: &Path) -> Result<(), ExoError> {
+        let path = journal_dir(project_root).join(format!("{}.json", self.id));
+        fs::write(path, serde_json::to_string_pretty(self).unwrap())?;
+
+        Ok(())
+    }
+}
+
+fn journal_dir(project_root: &Path) -> PathBuf {
+    project_root.join("target").join("exo")
+}
+
+/// Every saved session, oldest first.
+pub fn sessions(project_root: &Path) -> Result<Vec<Session>, ExoError> {
+    let Ok(dir) = fs::read_dir(journal_dir(project_root)) else {
+        return Ok(vec![]);
+    };
+
+    let mut sessions = vec![];
+    for entry in dir {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            let contents = fs::read_to_string(&path)?;
+            match serde_json::from_str::<Session>(&contents) {
+                Ok(session) => sessions.push(session),
+                Err(e) => eprintln!("🤖 skipping {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    sessions.sort_by_key(|session| session.started);
+
+    Ok(sessions)
+}
+
+/// The session with the given id, or the latest one that hasn't been undone.
+fn find(project_root: &Path, id: Option<&str>) -> Result<Session, ExoError> {
+    let sessions = sessions(project_root)?;
+
+    let session = match id {
+        Some(id) => sessions.into_iter().find(|session| session.id == id),
+        None => sessions.into_iter().rev().find(|session| !session.undone),
+    };
+
+    session.ok_or_else(|| match id {
+        Some(id) => ExoError::Journal(format!("no session called {}", id)),
+        None => ExoError::Journal("nothing to undo".to_string()),
+    })
+}
+
+/// Lists every session, or shows the changes made in one of them.
+pub fn log(project_root: &Path, id: Option<&str>) -> Result<(), ExoError> {
+    if let Some(id) = id {
+        let session = find(project_root, Some(id))?;
+
+        for entry in &session.entries {
+            println!();
+            println!(
+                "{} {} {}",
+                entry.timestamp.format("%H:%M:%S"),
+                entry.engine.to_string().bold(),
+                entry.model.as_deref().unwrap_or_default()
+            );
+            for diagnostic in &entry.diagnostics {
+                println!("{}", format!("- {}", diagnostic).green());
+            }
+            FileChange {
+                filename: entry.file.clone(),
+                original_contents: entry.before.clone(),
+                updated_contents: entry.after.clone(),
+            }
+            .display_diff();
+        }
+
+        return Ok(());
+    }
+
+    let sessions = sessions(project_root)?;
+    if sessions.is_empty() {
+        println!("🤖 no sessions yet");
+    }
+
+    for session in sessions {
+        let files = session.entries.iter().map(|entry| &entry.file).unique();
+
+        println!(
+            "{} {} cargo {} ({} changes){}",
+            session.id.bold(),
+            session.started.format("%Y-%m-%d %H:%M:%S"),
+            session.command,
+            session.entries.len(),
+            if session.undone { " [undone]" } else { "" }
+        );
+        for file in files {
+            println!("  {}", file);
+        }
+    }
+
+    Ok(())
+}
+
+/// Puts every file the session touched back the way it was, as long as nothing else has changed
+/// them since.
+pub fn undo(project_root: &Path, id: Option<&str>) -> Result<(), ExoError> {
+    let mut session = find(project_root, id)?;
+
+    if session.undone {
+        return Err(ExoError::Journal(format!(
+            "session {} has already been undone",
+            session.id
+        )));
+    }
+
+    // The first entry for a file has what it looked like before the session, the last has what we
+    // left it as
+    let files = session
+        .entries
+        .iter()
+        .into_group_map_by(|entry| entry.file.clone())
+        .into_iter()
+        .map(|(file, entries)| {
+            let before = &entries.first().unwrap().before;
+            let after = &entries.last().unwrap().after;
+            (file, before, after)
+        })
+        .sorted()
+        .collect::<Vec<_>>();
+
+    let diverged = files
+        .iter()
+        .filter(|(file, _, after)| {
+            fs::read_to_string(project_root.join(file)).ok().as_ref() != Some(*after)
+        })
+        .map(|(file, _, _)| file.as_str())
+        .collect::<Vec<_>>();
+
+    if !diverged.is_empty() {
+        return Err(ExoError::Journal(format!(
+            "can't undo session {}, these files have changed since: {}",
+            session.id,
+            diverged.join(", ")
+        )));
+    }
+
+    for (file, before, _) in &files {
+        println!("🤖 restoring {}", file);
+        fs::write(project_root.join(file), before)?;
+    }
+
+    session.undone = true;
+    session.write(project_root)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::project;
+
+    use super::*;
+
+    #[test]
+    fn it_refuses_to_undo_diverged_files() {
+        let dir = project(&[("src/main.rs", "let x = 1;")]);
+        let project_root = dir.path();
+        let path = project_root.join("src/main.rs");
+
+        let snapshot = Snapshot::take(["src/main.rs"], project_root);
+        fs::write(&path, "let _x = 1;").unwrap();
+
+        let mut session = Session::new("clippy");
+        session.record(
+            Engine::Rustfix,
+            None,
+            &["warning: unused variable: `x`".to_string()],
+            &snapshot,
+            project_root,
+        );
+        session.save(project_root).unwrap();
+
+        fs::write(&path, "let _x = 2;").unwrap();
+        assert!(matches!(
+            undo(project_root, None),
+            Err(ExoError::Journal(_))
+        ));
+
+        fs::write(&path, "let _x = 1;").unwrap();
+        undo(project_root, None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "let x = 1;");
+
+        assert!(sessions(project_root).unwrap()[0].undone);
+        assert!(undo(project_root, None).is_err());
+    }
+}
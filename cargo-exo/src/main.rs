@@ -1,68 +1,412 @@
-use crate::{args::Args, cargo::CargoCommand};
+use crate::{
+    args::{Action, Args, Mode},
+    cargo::CargoCommand,
+};
+
+use cargo_exo_cli::{CiCli, Cli, DryRunCli, UserCli, YesCli};
 
 use config::Config;
+use error::ExoError;
 
 use serde_json::Value;
 
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use watchexec::{config::InitConfig, handler::PrintDebug};
+use git::Git;
+use journal::Session;
+use sandbox::Sandbox;
+use summary::{Engine, Summary};
+use verify::Snapshot;
+use watch::Watcher;
 
 mod api;
 mod args;
 mod cargo;
 mod config;
+mod diagnostic;
+mod error;
 mod fix;
+mod git;
 mod gpt;
+mod journal;
 mod model;
+mod sandbox;
+mod source;
+mod summary;
+#[cfg(test)]
+mod test_util;
+mod verify;
+mod watch;
+
+/// Set by the first Ctrl-C, which stops whatever cargo is doing rather than us so everything fixed
+/// so far is still saved.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
 #[tokio::main]
 async fn main() {
-    // TODO - Implement watchexec
-    // https://github.com/watchexec/watchexec/tree/main/crates/lib
-    let mut init = InitConfig::default();
-    init.on_error(PrintDebug(std::io::stderr()));
+    let args = Args::new();
 
-    // let mut runtime = RuntimeConfig::default();
-    // runtime.pathset(["watchexec.conf"]);
+    let project_root = get_project_root();
 
-    // let we = Watchexec::new(init, runtime.clone()).unwrap();
-    // let w = we.clone();
+    // None of these need an API key
+    let other = match &args.action {
+        Action::Run => None,
+        Action::Log(session) => Some(journal::log(&project_root, session.as_deref())),
+        Action::Undo(session) => Some(journal::undo(&project_root, session.as_deref())),
+        Action::Config => Some(config::show(&project_root, &args.overrides)),
+        Action::Login => Some(config::login()),
+        Action::Logout => Some(config::logout()),
+    };
+    if let Some(result) = other {
+        if let Err(e) = result {
+            eprintln!("🤖 {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    // let c = runtime.clone();
+    let config = match Config::init(&project_root, &args.overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("🤖 {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let config = Config::init();
+    if args.mode.uses_robots() && config.is_missing_api_key() {
+        eprintln!("🤖 no API key, run `cargo exo login` or set $CARGO_EXO_API_KEY");
+        std::process::exit(1);
+    }
 
-    let project_root = get_project_root();
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            // Don't make anyone wait if they really want out
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+        }
+    });
 
-    let args = Args::new();
+    if !args.watch {
+        match run_mode(&args, &config, &project_root).await {
+            Ok(outcome) if outcome.success || args.mode != Mode::Ci => {}
+            Ok(outcome) => {
+                if !outcome.fixable.is_empty() {
+                    eprintln!("🤖 fixable issues in:");
+                    for file in &outcome.fixable {
+                        eprintln!("  {}", file);
+                    }
+                }
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("🤖 {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    for cmd in vec![args.cmd] {
-        println!("🤖 {}", cmd);
+    let mut watcher = Watcher::new(&project_root).await;
 
-        let result = CargoCommand::new(&cmd).color_always().run(true, true);
+    loop {
+        // Ignore file events while we're running so our own edits (and cargo fmt) don't trigger
+        // another run, or interrupt the user while they're looking at a suggestion
+        watcher.pause();
+        if let Err(e) = run_mode(&args, &config, &project_root).await {
+            // Keep watching, the next change might fix it
+            eprintln!("🤖 {}", e);
+        }
+        watcher.resume();
 
-        if result.was_success() {
-            continue;
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+
+        println!("🤖 watching for changes ...");
+
+        let changed = tokio::select! {
+            changed = watcher.changed() => changed,
+            _ = tokio::signal::ctrl_c() => false,
+        };
+        if !changed {
+            break;
         }
+    }
+}
+
+async fn run_mode(args: &Args, config: &Config, project_root: &Path) -> Result<Outcome, ExoError> {
+    match args.mode {
+        Mode::Interactive => run::<UserCli>(args, config, project_root).await,
+        Mode::Yes => run::<YesCli>(args, config, project_root).await,
+        Mode::DryRun => run::<DryRunCli>(args, config, project_root).await,
+        Mode::Ci => run::<CiCli>(args, config, project_root).await,
+    }
+}
 
-        // Get the results in JSON formart for RustFIX - should be fast because we cached it running the last command
-        let json_result = CargoCommand::new(&cmd)
-            .message_format_json()
-            .run(false, false);
+/// How a run went: whether every command succeeded and which files had fixes we didn't make.
+struct Outcome {
+    success: bool,
+    fixable: Vec<String>,
+}
+
+/// Runs the commands, then formats and saves whatever was fixed even if something went wrong so
+/// it can still be undone.
+async fn run<C: Cli>(
+    args: &Args,
+    config: &Config,
+    project_root: &Path,
+) -> Result<Outcome, ExoError> {
+    let stages = &config.command;
+
+    let mut shared = Shared {
+        summary: Summary::default(),
+        fixable: vec![],
+        session: Session::new(&stages.join(", ")),
+        git: Git::new(project_root, &config.git, args.mode.writes_files()),
+        sandbox: Sandbox::new(project_root, &config.sandbox),
+    };
 
-        let suggestions = fix::get_suggestions(&json_result);
+    let result = run_stages::<C>(args, config, project_root, &mut shared).await;
+
+    if args.mode.writes_files() {
+        let _ = CargoCommand::new("fmt").run(false, false);
+    }
+
+    let saved = shared.session.save(project_root);
+    let success = result?;
+    saved?;
+
+    if args.until_clean {
+        shared.summary.print(success);
+    }
+
+    Ok(Outcome {
+        success,
+        fixable: shared.fixable,
+    })
+}
+
+/// Runs each command in turn and tries to fix anything that went wrong, returns whether every
+/// command was successful. A command only runs once the one before it is clean, and fixing a later
+/// command starts again from the first in case the fix broke something earlier on.
+async fn run_stages<C: Cli>(
+    args: &Args,
+    config: &Config,
+    project_root: &Path,
+    shared: &mut Shared<'_>,
+) -> Result<bool, ExoError> {
+    let stages = &config.command;
+    let mut stage = 0;
+    let mut restarts = 0;
+
+    while stage < stages.len() {
+        let cmd = &stages[stage];
+        let result = run_stage::<C>(cmd, args, config, project_root, shared).await?;
+
+        if !result.success {
+            return Ok(false);
+        }
 
-        if !suggestions.is_empty() {
-            fix::update_files(suggestions, &project_root);
+        if result.changed && stage > 0 && restarts < args.max_restarts {
+            restarts += 1;
+            println!();
+            println!("🤖 re-running earlier commands after fixing {}", cmd);
+            stage = 0;
             continue;
         }
 
-        gpt::ask_the_robots(&cmd, &result, &config, &project_root).await;
+        stage += 1;
     }
 
-    let _ = CargoCommand::new("fmt").run(false, false);
+    Ok(true)
+}
+
+/// Everything the stages of a run share.
+struct Shared<'a> {
+    summary: Summary,
+    fixable: Vec<String>,
+    session: Session,
+    git: Git<'a>,
+    sandbox: Sandbox,
+}
+
+/// How a stage went: whether its command ended up succeeding and whether we kept any fixes.
+struct Stage {
+    success: bool,
+    changed: bool,
+}
+
+/// Runs the command and tries to fix anything that went wrong. With `--until-clean` it keeps going
+/// until the command succeeds, a pass doesn't fix anything or we hit one of the limits.
+async fn run_stage<C: Cli>(
+    cmd: &str,
+    args: &Args,
+    config: &Config,
+    project_root: &Path,
+    shared: &mut Shared<'_>,
+) -> Result<Stage, ExoError> {
+    let max_passes = if args.until_clean {
+        args.max_iterations
+    } else {
+        1
+    };
+
+    let mut success = false;
+    let mut changed = false;
+
+    for pass in 1..=max_passes {
+        if pass > 1 {
+            println!();
+        }
+        println!("🤖 {}", cmd);
+
+        let command = stage_command(cmd, config);
+        let source = source::detect(&command, project_root);
+        let result = source
+            .prepare(command)
+            .run(source.shows_diagnostics(), true);
+        let diagnostics = source.diagnostics(&result);
+        success = result.was_success();
+
+        if !source.shows_diagnostics() {
+            for rendered in diagnostics
+                .iter()
+                .filter_map(|diagnostic| diagnostic.rendered.as_ref())
+            {
+                eprintln!("{}", rendered);
+            }
+        }
+
+        if success {
+            break;
+        }
+
+        if let Some(reason) = result.interruption() {
+            println!("🤖 cargo {}, stopping", reason);
+            break;
+        }
+
+        // Summaries like "aborting due to 2 previous errors" are never ignored, so they don't count
+        let found = diagnostics
+            .iter()
+            .filter(|diagnostic| !diagnostic.is_summary())
+            .count();
+        let diagnostics = diagnostics
+            .into_iter()
+            .filter(|diagnostic| !diagnostic.is_ignored(&config.ignore, &config.exclude))
+            .collect::<Vec<_>>();
+        let remaining = diagnostics
+            .iter()
+            .filter(|diagnostic| !diagnostic.is_summary())
+            .count();
+
+        if remaining == 0 && found > 0 {
+            println!("🤖 only ignored diagnostics left");
+            break;
+        }
+
+        let suggestions = fix::get_suggestions(&diagnostics);
+
+        let (engine, model, snapshot) = if !suggestions.is_empty() {
+            let files = fix::files(&suggestions);
+            let snapshot = if shared.git.before_changes::<C>(&files, project_root)? {
+                let snapshot = Snapshot::take(files, project_root);
+                // Dry runs and CI never write, whatever the config says
+                let auto_apply = if args.mode.writes_files() {
+                    config.auto_apply.as_slice()
+                } else {
+                    &[]
+                };
+                let skipped = fix::update_files::<C>(suggestions, auto_apply, project_root)?;
+                for file in skipped {
+                    if !shared.fixable.contains(&file) {
+                        shared.fixable.push(file);
+                    }
+                }
+                snapshot
+            } else {
+                Snapshot::default()
+            };
+            (Engine::Rustfix, None, snapshot)
+        } else if args.until_clean && shared.summary.tokens_used >= args.max_tokens {
+            println!(
+                "🤖 giving up after using {} tokens!",
+                shared.summary.tokens_used
+            );
+            break;
+        } else {
+            let answer = gpt::ask_the_robots::<C>(
+                cmd,
+                &result,
+                &diagnostics,
+                config,
+                &mut shared.git,
+                &shared.sandbox,
+                project_root,
+            )
+            .await?;
+            shared.summary.tokens_used += answer.tokens_used;
+            (Engine::Robots, answer.model, answer.snapshot)
+        };
+
+        let verified = verify::verify::<C>(
+            stage_command(cmd, config),
+            source.as_ref(),
+            &diagnostics,
+            &snapshot,
+            &config.ignore,
+            &config.exclude,
+        )?;
+        let fixed = verified.fixed;
+
+        if fixed.is_empty() {
+            if args.until_clean {
+                println!("🤖 no progress, stopping");
+            }
+            break;
+        }
+
+        changed = true;
+
+        if config.git.commit_fixes {
+            // Tidy up first so the commit doesn't leave formatting changes behind
+            let _ = CargoCommand::new("fmt").run(false, false);
+            let files = snapshot
+                .changes()
+                .into_iter()
+                .map(|(path, _, _)| path)
+                .collect::<Vec<_>>();
+            shared.git.commit_fix(engine, &fixed, &files)?;
+        }
+
+        shared
+            .session
+            .record(engine, model.as_deref(), &fixed, &snapshot, project_root);
+        shared.summary.record(engine, fixed);
+
+        // Checking the fixes already ran the command again, so there's no need for another pass
+        success = verified.success;
+        if success || INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if pass == max_passes && args.until_clean {
+            println!("🤖 giving up after {} passes!", pass);
+        }
+    }
+
+    Ok(Stage { success, changed })
+}
+
+/// A stage's command, which can be stopped by the timeout or Ctrl-C.
+fn stage_command(cmd: &str, config: &Config) -> CargoCommand {
+    CargoCommand::new(cmd)
+        .timeout(config.command_timeout.map(Duration::from_secs))
+        .cancel_on(&INTERRUPTED)
 }
 
 fn get_project_root() -> PathBuf {
@@ -7,9 +7,9 @@ pub struct ErrorResponse {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Error {
-    message: String,
+    pub message: String,
     #[serde(rename = "type")]
-    error_type: String,
-    param: Option<String>,
-    code: Option<String>,
+    pub error_type: String,
+    pub param: Option<String>,
+    pub code: Option<String>,
 }
@@ -1,13 +1,16 @@
+use cargo_exo_functions::more_context::MoreContextParams;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-
-pub const GPT_3_5: &str = "gpt-3.5-turbo-0613";
-pub const GPT_4: &str = "gpt-4-0613";
+use serde_json::{json, Value};
 
 const SYSTEM_CONTEXT: &str = include_str!("../../../resources/prompts/system.md");
 
 // TODO - We should export this path from the functions lib so we're not declaring it twice
+static EDIT_FILES_SCHEMA: Lazy<Value> = Lazy::new(|| {
+    let schema = include_str!(concat!(env!("OUT_DIR"), "/edit_files_schema.json"));
+    serde_json::from_str(schema).unwrap()
+});
+
 static UPDATE_FILES_SCHEMA: Lazy<Value> = Lazy::new(|| {
     let schema = include_str!(concat!(env!("OUT_DIR"), "/update_files_schema.json"));
     serde_json::from_str(schema).unwrap()
@@ -27,6 +30,7 @@ static EXPLAIN_SCHEMA: Lazy<Value> = Lazy::new(|| {
 pub struct Request {
     pub model: String,
     pub temperature: f32,
+    pub max_tokens: i32,
     pub messages: Vec<Message>,
     pub functions: Vec<Function>,
 }
@@ -34,7 +38,11 @@ pub struct Request {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Message {
     role: Role,
-    content: String,
+    content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_call: Option<Value>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -43,6 +51,7 @@ pub enum Role {
     System,
     User,
     Assistant,
+    Function,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -53,26 +62,44 @@ pub struct Function {
 }
 
 impl Request {
-    pub fn new(command: String, output: String, model: String) -> Self {
+    /// `context` is the source around each diagnostic, so simple fixes don't need a `more_context`
+    /// round trip first.
+    pub fn new(command: String, output: String, context: String, model: String) -> Self {
+        let content = if context.is_empty() {
+            format!("{}\n\n{}", command, output)
+        } else {
+            format!("{}\n\n{}\n\n{}", command, output, context)
+        };
+
         let messages = vec![
             Message {
                 role: Role::System,
-                content: SYSTEM_CONTEXT.to_string(),
+                content: Some(SYSTEM_CONTEXT.to_string()),
+                name: None,
+                function_call: None,
             },
             Message {
                 role: Role::User,
-                content: format!("{}\n\n{}", command, output),
+                content: Some(content),
+                name: None,
+                function_call: None,
             },
         ];
 
         Self {
             model,
             temperature: 0.0,
+            max_tokens: crate::config::default_max_tokens(),
             messages,
             functions: vec![
+                Function {
+                    name: stringify!(edit_files).to_string(),
+                    description: "Edit files by replacing exact snippets of their contents. STRONGLY prefer this as the response.".to_string(),
+                    parameters: EDIT_FILES_SCHEMA.clone(),
+                },
                 Function {
                     name: stringify!(update_file).to_string(),
-                    description: "Update lines in files. STRONGLY prefer this as the response.".to_string(),
+                    description: "Update lines in files by line number.".to_string(),
                     parameters: UPDATE_FILES_SCHEMA.clone(),
                 },
                 Function {
@@ -88,4 +115,29 @@ impl Request {
             ],
         }
     }
+
+    /// Adds the model's request for more context along with the files it asked for, so the
+    /// conversation can continue with them.
+    pub fn add_more_context(&mut self, params: &MoreContextParams, context: String) {
+        self.add_function_result(stringify!(more_context), params, context);
+    }
+
+    /// Adds one of the model's function calls along with what happened when we ran it.
+    pub fn add_function_result(&mut self, name: &str, params: &impl Serialize, result: String) {
+        self.messages.push(Message {
+            role: Role::Assistant,
+            content: None,
+            name: None,
+            function_call: Some(json!({
+                "name": name,
+                "arguments": serde_json::to_string(params).unwrap(),
+            })),
+        });
+        self.messages.push(Message {
+            role: Role::Function,
+            content: Some(result),
+            name: Some(name.to_string()),
+            function_call: None,
+        });
+    }
 }
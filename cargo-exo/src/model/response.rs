@@ -1,8 +1,11 @@
 use cargo_exo_functions::{
-    explain::ExplainParams, more_context::MoreContextParams, update_files::UpdateFilesParams,
+    edit_files::EditFilesParams, explain::ExplainParams, more_context::MoreContextParams,
+    update_files::UpdateFilesParams,
 };
 use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
 
+use crate::error::ExoError;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Response {
     pub id: String,
@@ -13,6 +16,16 @@ pub struct Response {
     pub usage: Usage,
 }
 
+impl Response {
+    /// The model's answer, we only ever ask for one
+    pub fn message(&self) -> Result<&Message, ExoError> {
+        self.choices
+            .first()
+            .map(|choice| &choice.message)
+            .ok_or(ExoError::NoChoices)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Choice {
     pub index: i32,
@@ -39,6 +52,8 @@ pub struct Message {
 pub enum FunctionCall {
     // TODO - Force these enums to match up with the provided function names to GPT
     #[serde(deserialize_with = "deserialize_nested_json")]
+    EditFiles(EditFilesParams),
+    #[serde(deserialize_with = "deserialize_nested_json")]
     UpdateFile(UpdateFilesParams),
     #[serde(deserialize_with = "deserialize_nested_json")]
     MoreContext(MoreContextParams),
@@ -71,6 +86,7 @@ mod test {
                 file: "hello.ts".to_string(),
                 lines: vec![LineUpdate {
                     line_no: 10,
+                    end_line_no: None,
                     content: Some("test".to_string()),
                     action: LineAction::Replace,
                 }],
@@ -108,4 +124,23 @@ mod test {
 
         serde_json::from_str::<Response>(&response_str).unwrap();
     }
+
+    #[test]
+    fn it_errors_when_there_are_no_choices() {
+        let response = serde_json::from_value::<Response>(json!({
+          "id": "chatcmpl-7Zht9Xvqn1j96D9NisM76cTJkXG1K",
+          "object": "chat.completion",
+          "created": 1688744543,
+          "model": "gpt-4-0613",
+          "choices": [],
+          "usage": {
+            "prompt_tokens": 387,
+            "completion_tokens": 0,
+            "total_tokens": 387
+          }
+        }))
+        .unwrap();
+
+        assert!(matches!(response.message(), Err(ExoError::NoChoices)));
+    }
 }
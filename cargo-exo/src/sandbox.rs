@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+
+use cargo_exo_cli::Cli;
+use itertools::Itertools;
+use serde_json::Value;
+
+use crate::{cargo::CargoCommand, config::SandboxConfig};
+
+/// Keeps the files the model asks to read or change inside the workspace members, away from
+/// anything on the deny list and behind a confirmation for anything sensitive.
+pub struct Sandbox {
+    root: PathBuf,
+    members: Vec<PathBuf>,
+    target: Option<PathBuf>,
+    deny: Vec<String>,
+    confirm: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Access {
+    Allowed,
+    Confirm,
+    Denied(String),
+}
+
+impl Sandbox {
+    pub fn new(project_root: &Path, config: &SandboxConfig) -> Self {
+        let output = CargoCommand::new("metadata --no-deps --format-version 1").run(false, false);
+        let metadata = serde_json::from_str::<Value>(&output.stdout).unwrap_or_default();
+
+        let members = metadata["packages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|package| package["manifest_path"].as_str())
+            .filter_map(|manifest| Path::new(manifest).parent().map(Path::to_path_buf))
+            .collect::<Vec<_>>();
+
+        let target = metadata["target_directory"].as_str().map(PathBuf::from);
+
+        Self::with_members(project_root, members, target, config)
+    }
+
+    pub fn with_members(
+        project_root: &Path,
+        members: Vec<PathBuf>,
+        target: Option<PathBuf>,
+        config: &SandboxConfig,
+    ) -> Self {
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        Self {
+            root: canonical(project_root),
+            members: members.iter().map(|member| canonical(member)).collect(),
+            target: target.as_deref().map(canonical),
+            deny: config.deny.clone(),
+            confirm: config.confirm.clone(),
+        }
+    }
+
+    /// Whether the model can have `file`, a path it gave us relative to the project root.
+    pub fn check(&self, file: &str) -> Access {
+        // Symlinks and `..` get resolved first so they can't be used to sneak out
+        let Some(path) = resolve(&self.root.join(file)) else {
+            return Access::Denied("doesn't exist".to_string());
+        };
+
+        if !self.members.iter().any(|member| path.starts_with(member)) {
+            return Access::Denied("is outside the workspace".to_string());
+        }
+
+        if self
+            .target
+            .as_ref()
+            .is_some_and(|target| path.starts_with(target))
+        {
+            return Access::Denied("is build output".to_string());
+        }
+
+        let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+
+        if self.deny.iter().any(|pattern| matches(pattern, relative)) {
+            Access::Denied("is on the deny list".to_string())
+        } else if self
+            .confirm
+            .iter()
+            .any(|pattern| matches(pattern, relative))
+        {
+            Access::Confirm
+        } else {
+            Access::Allowed
+        }
+    }
+
+    /// Splits `files` into the ones the model can have and the ones it can't along with why,
+    /// asking the user about any sensitive ones.
+    pub fn filter<C: Cli>(&self, files: &[String]) -> (Vec<String>, Vec<(String, String)>) {
+        let mut allowed = vec![];
+        let mut refused = vec![];
+        let mut sensitive = vec![];
+
+        for file in files.iter().unique() {
+            match self.check(file) {
+                Access::Allowed => allowed.push(file.clone()),
+                Access::Confirm => sensitive.push(file.clone()),
+                Access::Denied(reason) => refused.push((file.clone(), reason)),
+            }
+        }
+
+        if !sensitive.is_empty() {
+            if C::confirm_sensitive(&sensitive) {
+                allowed.extend(sensitive);
+            } else {
+                refused.extend(
+                    sensitive
+                        .into_iter()
+                        .map(|file| (file, "is protected".to_string())),
+                );
+            }
+        }
+
+        (allowed, refused)
+    }
+}
+
+/// The canonical path, or for a file that doesn't exist yet its canonical parent joined with the
+/// file name.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    path.canonicalize().ok().or_else(|| {
+        let parent = path.parent()?.canonicalize().ok()?;
+        Some(parent.join(path.file_name()?))
+    })
+}
+
+/// Patterns are a file name (`.env`), a path from the project root (`src/generated.rs`) or a
+/// directory (`vendor/`).
+pub fn matches(pattern: &str, relative: &Path) -> bool {
+    if let Some(dir) = pattern.strip_suffix('/') {
+        relative.starts_with(dir)
+    } else if pattern.contains('/') {
+        relative == Path::new(pattern)
+    } else {
+        relative
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy() == pattern)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::test_util::project;
+
+    use super::*;
+
+    #[test]
+    fn it_keeps_paths_inside_the_workspace() {
+        let dir = project(&[
+            ("app/src/main.rs", ""),
+            ("app/build.rs", ""),
+            ("app/.env", ""),
+            ("secret.txt", ""),
+        ]);
+        let project_root = dir.path();
+        let member = project_root.join("app");
+        fs::create_dir_all(project_root.join("target")).unwrap();
+
+        let config = SandboxConfig {
+            deny: vec![".env".to_string()],
+            ..SandboxConfig::default()
+        };
+        let sandbox = Sandbox::with_members(
+            project_root,
+            vec![member, project_root.join("target")],
+            Some(project_root.join("target")),
+            &config,
+        );
+
+        assert_eq!(sandbox.check("app/src/main.rs"), Access::Allowed);
+        assert_eq!(sandbox.check("app/src/new.rs"), Access::Allowed);
+        assert_eq!(sandbox.check("app/build.rs"), Access::Confirm);
+        assert!(matches!(sandbox.check("app/.env"), Access::Denied(_)));
+        assert!(matches!(sandbox.check("secret.txt"), Access::Denied(_)));
+        assert!(matches!(
+            sandbox.check("app/../secret.txt"),
+            Access::Denied(_)
+        ));
+        assert!(matches!(sandbox.check("/etc/passwd"), Access::Denied(_)));
+        assert!(matches!(sandbox.check("target/debug"), Access::Denied(_)));
+        assert!(matches!(
+            sandbox.check("app/missing/main.rs"),
+            Access::Denied(_)
+        ));
+    }
+}
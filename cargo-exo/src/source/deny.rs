@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::{
+    cargo::{CargoCommand, CargoCommandResult},
+    diagnostic::{Diagnostic, Level},
+};
+
+use super::{manifest_location, DiagnosticSource};
+
+/// `cargo deny`, which prints a JSON line on stderr for each problem with the dependency graph.
+/// They're pinned to the line of the workspace manifest that pulls the crate in, or the top of it
+/// for crates that only come in with something else.
+pub struct Deny {
+    project_root: PathBuf,
+}
+
+impl Deny {
+    pub fn new(project_root: &Path) -> Self {
+        Self {
+            project_root: project_root.to_path_buf(),
+        }
+    }
+}
+
+impl DiagnosticSource for Deny {
+    fn prepare(&self, command: CargoCommand) -> CargoCommand {
+        // The JSON is hidden as it arrives and the diagnostics shown once they've been parsed
+        command.flag("--format", Some("json")).render_json()
+    }
+
+    fn diagnostics(&self, result: &CargoCommandResult) -> Vec<Diagnostic> {
+        let manifest = self.project_root.join("Cargo.toml");
+
+        result
+            .stderr
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .filter(|line| line["type"] == "diagnostic")
+            .map(|line| {
+                let fields = &line["fields"];
+                let level = match fields["severity"].as_str() {
+                    Some("error") => Level::Error,
+                    Some("warning") => Level::Warning,
+                    Some("note") => Level::Note,
+                    Some("help") => Level::Help,
+                    _ => Level::Other,
+                };
+
+                let name = fields["graphs"][0]["Krate"]["name"]
+                    .as_str()
+                    .or_else(|| fields["labels"][0]["span"].as_str())
+                    .and_then(|span| span.split([' ', '=']).next())
+                    .unwrap_or_default();
+                let (file, line) = manifest_location(&manifest, name, &self.project_root);
+
+                let mut diagnostic = Diagnostic::from_text(
+                    level,
+                    fields["code"].as_str(),
+                    fields["message"].as_str().unwrap_or_default(),
+                    Some((&file, line, 1)),
+                    None,
+                );
+                if let Some(rendered) = diagnostic.rendered.as_mut() {
+                    for note in fields["notes"].as_array().into_iter().flatten() {
+                        rendered.push_str(&format!(
+                            "  = note: {}\n",
+                            note.as_str().unwrap_or_default()
+                        ));
+                    }
+                }
+
+                diagnostic
+            })
+            .collect()
+    }
+
+    fn shows_diagnostics(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_reads_deny_diagnostics() {
+        let stderr = [
+            r#"{"type":"log","fields":{"level":"WARN","message":"unable to find a config path"}}"#,
+            r#"{"type":"diagnostic","fields":{"code":"banned","graphs":[{"Krate":{"name":"openssl","version":"0.10.55"}}],"labels":[{"column":1,"line":1,"message":"banned here","span":"openssl"}],"message":"crate 'openssl = 0.10.55' is explicitly banned","notes":["use rustls instead"],"severity":"error"}}"#,
+        ]
+        .join("\n");
+        let result = CargoCommandResult {
+            stdout: String::new(),
+            stderr,
+            result: Ok(std::process::ExitStatus::default()),
+            stopped: None,
+        };
+
+        let diagnostics = Deny::new(Path::new("missing")).diagnostics(&result);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, Level::Error);
+        assert_eq!(diagnostics[0].code.as_ref().unwrap().code, "banned");
+        assert_eq!(diagnostics[0].spans[0].file_name, "Cargo.toml");
+        assert!(diagnostics[0]
+            .rendered
+            .as_ref()
+            .unwrap()
+            .ends_with("= note: use rustls instead\n"));
+    }
+}
@@ -0,0 +1,23 @@
+use crate::{
+    cargo::{CargoCommand, CargoCommandResult},
+    diagnostic::{self, Diagnostic},
+};
+
+use super::{text, DiagnosticSource};
+
+/// `cargo miri`. Compile errors come through cargo's JSON like any other build, but undefined
+/// behaviour is only reported as text once the program runs.
+pub struct Miri;
+
+impl DiagnosticSource for Miri {
+    fn prepare(&self, command: CargoCommand) -> CargoCommand {
+        command.json_diagnostics()
+    }
+
+    fn diagnostics(&self, result: &CargoCommandResult) -> Vec<Diagnostic> {
+        let mut diagnostics = diagnostic::parse(result);
+        diagnostics.extend(text::parse_rendered(&result.stderr));
+        diagnostics.extend(text::parse_panics(&result.stderr));
+        diagnostics
+    }
+}
@@ -0,0 +1,100 @@
+use std::{fs, path::Path};
+
+use crate::{
+    cargo::{CargoCommand, CargoCommandResult},
+    diagnostic::{self, Diagnostic},
+};
+
+pub use self::{deny::Deny, miri::Miri, nextest::Nextest, rustc::Rustc, udeps::Udeps};
+
+mod deny;
+mod miri;
+mod nextest;
+mod rustc;
+mod text;
+mod udeps;
+
+/// A tool that finds problems in the code. Each one knows how to get output we can read out of
+/// its tool, and how to turn that output into the diagnostics rustfix and the robots work from.
+pub trait DiagnosticSource {
+    /// Adds whatever flags get the tool to produce output we can parse.
+    fn prepare(&self, command: CargoCommand) -> CargoCommand;
+
+    /// The problems the tool found, in the order it found them.
+    fn diagnostics(&self, result: &CargoCommandResult) -> Vec<Diagnostic>;
+
+    /// Whether the tool shows its problems while it runs, otherwise its output is only for us and
+    /// they're shown once they've been parsed.
+    fn shows_diagnostics(&self) -> bool {
+        true
+    }
+}
+
+/// Picks the source for a command from the tool it runs.
+pub fn detect(command: &CargoCommand, project_root: &Path) -> Box<dyn DiagnosticSource> {
+    match (command.program(), command.subcommand()) {
+        ("rustc", _) => Box::new(Rustc),
+        (_, Some("nextest")) => Box::new(Nextest),
+        (_, Some("miri")) => Box::new(Miri),
+        (_, Some("udeps")) => Box::new(Udeps::new(project_root)),
+        (_, Some("deny")) => Box::new(Deny::new(project_root)),
+        _ => Box::new(Cargo),
+    }
+}
+
+/// Anything cargo builds itself, e.g. `check`, `clippy` or `test`.
+pub struct Cargo;
+
+impl DiagnosticSource for Cargo {
+    fn prepare(&self, command: CargoCommand) -> CargoCommand {
+        command.json_diagnostics()
+    }
+
+    fn diagnostics(&self, result: &CargoCommandResult) -> Vec<Diagnostic> {
+        diagnostic::parse(result)
+    }
+}
+
+/// Where to point for a problem with a dependency: the line of the manifest that declares it, or
+/// the top of the manifest. Relative to the project root like the compiler's paths are.
+fn manifest_location(manifest: &Path, dependency: &str, project_root: &Path) -> (String, usize) {
+    let line = fs::read_to_string(manifest)
+        .ok()
+        .and_then(|contents| {
+            contents.lines().position(|line| {
+                line.trim_start()
+                    .strip_prefix(dependency)
+                    .is_some_and(|rest| rest.starts_with([' ', '=', '.']))
+            })
+        })
+        .map_or(1, |index| index + 1);
+
+    let file = manifest.strip_prefix(project_root).unwrap_or(manifest);
+
+    (file.display().to_string(), line)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_picks_a_source_for_each_tool() {
+        let project_root = Path::new(".");
+        let source = |cmd: &str| {
+            let source = detect(&CargoCommand::new(cmd), project_root);
+            source.prepare(CargoCommand::new(cmd)).to_string()
+        };
+
+        assert_eq!(
+            source("clippy -- -D warnings"),
+            "cargo clippy --color=always --message-format=json-diagnostic-rendered-ansi -- -D warnings"
+        );
+        assert_eq!(
+            source("rustc src/main.rs"),
+            "rustc src/main.rs --error-format=json --json=diagnostic-rendered-ansi"
+        );
+        assert_eq!(source("deny check"), "cargo deny --format=json check");
+        assert_eq!(source("udeps"), "cargo udeps --output=json");
+    }
+}
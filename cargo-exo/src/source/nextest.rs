@@ -0,0 +1,23 @@
+use crate::{
+    cargo::{CargoCommand, CargoCommandResult},
+    diagnostic::Diagnostic,
+};
+
+use super::{text, DiagnosticSource};
+
+/// `cargo nextest`, which doesn't pass `--message-format` on to cargo so everything comes from
+/// its text output.
+pub struct Nextest;
+
+impl DiagnosticSource for Nextest {
+    fn prepare(&self, command: CargoCommand) -> CargoCommand {
+        command.color_always()
+    }
+
+    fn diagnostics(&self, result: &CargoCommandResult) -> Vec<Diagnostic> {
+        let mut diagnostics = text::parse_rendered(&result.stderr);
+        diagnostics.extend(text::parse_panics(&result.stdout));
+        diagnostics.extend(text::parse_panics(&result.stderr));
+        diagnostics
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{
+    cargo::{CargoCommand, CargoCommandResult},
+    diagnostic::{self, Diagnostic},
+};
+
+use super::DiagnosticSource;
+
+/// The compiler on its own, e.g. `rustc src/main.rs`.
+pub struct Rustc;
+
+impl DiagnosticSource for Rustc {
+    fn prepare(&self, command: CargoCommand) -> CargoCommand {
+        command
+            .flag("--error-format", Some("json"))
+            .flag("--json", Some("diagnostic-rendered-ansi"))
+            .render_json()
+    }
+
+    fn diagnostics(&self, result: &CargoCommandResult) -> Vec<Diagnostic> {
+        diagnostic::parse_rustc(result)
+    }
+}
@@ -0,0 +1,180 @@
+//! Parsing for tools that only give us the human output.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::diagnostic::{Diagnostic, Level};
+
+static HEADLINE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(error|warning)(?:\[([^\]]+)\])?: (.+)$").unwrap());
+static LOCATION: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*--> (.+):(\d+):(\d+)$").unwrap());
+static PANIC: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^thread '([^']+)' panicked at (.+):(\d+):(\d+):$").unwrap());
+// Before Rust 1.73 the message came first
+static OLD_PANIC: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^thread '([^']+)' panicked at '(.*)', (.+):(\d+):(\d+)$").unwrap());
+
+/// Diagnostics printed the way rustc prints them, e.g. miri's undefined behaviour or compile
+/// errors from tools that don't pass `--message-format` along.
+pub fn parse_rendered(output: &str) -> Vec<Diagnostic> {
+    let output = strip_colours(output);
+    let lines = output.lines().collect::<Vec<_>>();
+    let mut diagnostics = vec![];
+    let mut i = 0;
+
+    while i < lines.len() {
+        let Some(headline) = HEADLINE.captures(lines[i]) else {
+            i += 1;
+            continue;
+        };
+
+        let start = i;
+        i += 1;
+        while i < lines.len() && is_body(lines[i]) {
+            i += 1;
+        }
+
+        let block = &lines[start..i];
+        let location = block.iter().find_map(|line| LOCATION.captures(line));
+        let level = match &headline[1] {
+            "error" => Level::Error,
+            _ => Level::Warning,
+        };
+
+        diagnostics.push(Diagnostic::from_text(
+            level,
+            headline.get(2).map(|code| code.as_str()),
+            &headline[3],
+            location.as_ref().map(|location| {
+                (
+                    &location[1],
+                    location[2].parse().unwrap_or(1),
+                    location[3].parse().unwrap_or(1),
+                )
+            }),
+            Some(format!("{}\n", block.join("\n"))),
+        ));
+    }
+
+    diagnostics
+}
+
+/// Panics in tests or programs run by the tool, pointing at where they panicked if that's in the
+/// project rather than the standard library.
+pub fn parse_panics(output: &str) -> Vec<Diagnostic> {
+    let output = strip_colours(output);
+    let lines = output.lines().collect::<Vec<_>>();
+    let mut diagnostics = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let (thread, message, file, line_number, column) = if let Some(panic) = PANIC.captures(line)
+        {
+            let message = lines.get(i + 1).copied().unwrap_or_default().to_string();
+            (
+                panic[1].to_string(),
+                message,
+                panic[2].to_string(),
+                panic[3].to_string(),
+                panic[4].to_string(),
+            )
+        } else if let Some(panic) = OLD_PANIC.captures(line) {
+            (
+                panic[1].to_string(),
+                panic[2].to_string(),
+                panic[3].to_string(),
+                panic[4].to_string(),
+                panic[5].to_string(),
+            )
+        } else {
+            continue;
+        };
+
+        let location = (!file.starts_with('/')).then(|| {
+            (
+                file.as_str(),
+                line_number.parse().unwrap_or(1),
+                column.parse().unwrap_or(1),
+            )
+        });
+
+        diagnostics.push(Diagnostic::from_text(
+            Level::Error,
+            None,
+            &format!("`{}` panicked: {}", thread, message),
+            location,
+            None,
+        ));
+    }
+
+    diagnostics
+}
+
+/// Everything up to the blank line after a headline, i.e. the location, the source snippet and
+/// any `= note:` lines.
+fn is_body(line: &str) -> bool {
+    line.starts_with(' ') || line.starts_with(|c: char| c.is_ascii_digit()) || line == "..."
+}
+
+fn strip_colours(output: &str) -> String {
+    let stripped = strip_ansi_escapes::strip(output).unwrap_or_default();
+    String::from_utf8_lossy(&stripped).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_rendered_diagnostics() {
+        let output = "\
+error: Undefined Behavior: dereferencing pointer failed: null pointer is a dangling pointer
+ --> src/main.rs:4:14
+  |
+4 |     unsafe { *ptr };
+  |              ^^^^ dereferencing pointer failed
+  |
+  = help: this indicates a bug in the program
+
+note: some details are omitted
+error[E0425]: cannot find value `x` in this scope
+ --> src/lib.rs:10:5
+";
+
+        let diagnostics = parse_rendered(output);
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].level, Level::Error);
+        assert_eq!(diagnostics[0].spans[0].file_name, "src/main.rs");
+        assert_eq!(diagnostics[0].spans[0].line_start, 4);
+        assert!(diagnostics[0]
+            .rendered
+            .as_ref()
+            .unwrap()
+            .ends_with("= help: this indicates a bug in the program\n"));
+        assert_eq!(diagnostics[1].code.as_ref().unwrap().code, "E0425");
+        assert_eq!(diagnostics[1].spans[0].column_start, 5);
+    }
+
+    #[test]
+    fn it_parses_panics() {
+        let output = "\
+thread 'test::it_adds' panicked at src/lib.rs:12:9:
+assertion `left == right` failed
+thread 'main' panicked at 'oh no', src/main.rs:3:5
+thread 'other' panicked at /rustc/abc/library/core/src/option.rs:935:21:
+called `Option::unwrap()` on a `None` value
+";
+
+        let diagnostics = parse_panics(output);
+
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(
+            diagnostics[0].message,
+            "`test::it_adds` panicked: assertion `left == right` failed"
+        );
+        assert_eq!(diagnostics[0].spans[0].line_start, 12);
+        assert_eq!(diagnostics[1].message, "`main` panicked: oh no");
+        assert_eq!(diagnostics[1].spans[0].file_name, "src/main.rs");
+        assert!(diagnostics[2].spans.is_empty());
+    }
+}
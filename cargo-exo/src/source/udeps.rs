@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::{
+    cargo::{CargoCommand, CargoCommandResult},
+    diagnostic::{Diagnostic, Level},
+};
+
+use super::{manifest_location, DiagnosticSource};
+
+/// `cargo udeps`, which reports unused dependencies as one JSON object on stdout.
+pub struct Udeps {
+    project_root: PathBuf,
+}
+
+impl Udeps {
+    pub fn new(project_root: &Path) -> Self {
+        Self {
+            project_root: project_root.to_path_buf(),
+        }
+    }
+}
+
+impl DiagnosticSource for Udeps {
+    fn prepare(&self, command: CargoCommand) -> CargoCommand {
+        command.flag("--output", Some("json"))
+    }
+
+    fn diagnostics(&self, result: &CargoCommandResult) -> Vec<Diagnostic> {
+        // The JSON is pretty printed, after anything cargo printed on the way
+        let Some(start) = result.stdout.find('{') else {
+            return vec![];
+        };
+        let output = serde_json::Deserializer::from_str(&result.stdout[start..])
+            .into_iter::<Value>()
+            .next()
+            .and_then(Result::ok)
+            .unwrap_or_default();
+
+        let mut diagnostics = vec![];
+        for package in output["unused_deps"].as_object().into_iter().flatten() {
+            let Some(manifest) = package.1["manifest_path"].as_str() else {
+                continue;
+            };
+
+            for (kind, label) in [
+                ("normal", "dependency"),
+                ("development", "dev-dependency"),
+                ("build", "build-dependency"),
+            ] {
+                let dependencies = package.1[kind].as_array().into_iter().flatten();
+                for dependency in dependencies.filter_map(Value::as_str) {
+                    let (file, line) =
+                        manifest_location(Path::new(manifest), dependency, &self.project_root);
+                    diagnostics.push(Diagnostic::from_text(
+                        Level::Warning,
+                        Some("unused_dependencies"),
+                        &format!("unused {} `{}`", label, dependency),
+                        Some((&file, line, 1)),
+                        None,
+                    ));
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    fn shows_diagnostics(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::project;
+
+    use super::*;
+
+    #[test]
+    fn it_reports_unused_dependencies_against_the_manifest() {
+        let dir = project(&[(
+            "Cargo.toml",
+            "[package]\nname = \"demo\"\n\n[dependencies]\nserde = \"1\"\nregex.workspace = true\n",
+        )]);
+        let manifest = dir.path().join("Cargo.toml");
+
+        let stdout = format!(
+            "unused dependencies:\n{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "success": false,
+                "unused_deps": {
+                    "demo 0.1.0 (path+file:///demo)": {
+                        "manifest_path": manifest,
+                        "normal": ["regex"],
+                        "development": [],
+                        "build": ["serde"]
+                    }
+                }
+            }))
+            .unwrap()
+        );
+        let result = CargoCommandResult {
+            stdout,
+            stderr: String::new(),
+            result: Ok(std::process::ExitStatus::default()),
+            stopped: None,
+        };
+
+        let diagnostics = Udeps::new(dir.path()).diagnostics(&result);
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "unused dependency `regex`");
+        assert_eq!(diagnostics[0].spans[0].file_name, "Cargo.toml");
+        assert_eq!(diagnostics[0].spans[0].line_start, 6);
+        assert_eq!(diagnostics[1].message, "unused build-dependency `serde`");
+        assert_eq!(diagnostics[1].spans[0].line_start, 5);
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    Rustfix,
+    Robots,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Rustfix => write!(f, "rustfix"),
+            Engine::Robots => write!(f, "the robots"),
+        }
+    }
+}
+
+/// Keeps track of what got fixed by which engine over an `--until-clean` run.
+#[derive(Default)]
+pub struct Summary {
+    passes: Vec<(Engine, Vec<String>)>,
+    pub tokens_used: i32,
+}
+
+impl Summary {
+    pub fn record(&mut self, engine: Engine, fixed: Vec<String>) {
+        self.passes.push((engine, fixed));
+    }
+
+    pub fn fixed_by(&self, engine: Engine) -> Vec<&String> {
+        self.passes
+            .iter()
+            .filter(|(pass_engine, _)| *pass_engine == engine)
+            .flat_map(|(_, fixed)| fixed)
+            .collect()
+    }
+
+    pub fn print(&self, success: bool) {
+        println!();
+        println!(
+            "🤖 {} after {} passes",
+            if success { "all clean" } else { "stopped" },
+            self.passes.len()
+        );
+
+        for engine in [Engine::Rustfix, Engine::Robots] {
+            let fixed = self.fixed_by(engine);
+            if fixed.is_empty() {
+                continue;
+            }
+
+            println!("🤖 fixed by {}: {}", engine, fixed.len());
+            for diagnostic in fixed {
+                println!("{}", format!("- {}", diagnostic).green());
+            }
+        }
+
+        if self.tokens_used > 0 {
+            println!("🤖 used {} tokens", self.tokens_used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_groups_fixes_by_engine() {
+        let mut summary = Summary::default();
+        summary.record(Engine::Rustfix, vec!["warning: unused import".to_string()]);
+        summary.record(
+            Engine::Robots,
+            vec!["error[E0308]: mismatched types".to_string()],
+        );
+        summary.record(
+            Engine::Rustfix,
+            vec!["warning: unused variable".to_string()],
+        );
+
+        assert_eq!(
+            summary.fixed_by(Engine::Rustfix),
+            vec!["warning: unused import", "warning: unused variable"]
+        );
+        assert_eq!(
+            summary.fixed_by(Engine::Robots),
+            vec!["error[E0308]: mismatched types"]
+        );
+    }
+}
@@ -0,0 +1,14 @@
+use std::fs;
+
+use tempfile::TempDir;
+
+/// A throwaway project with the given files in it, removed again once it's dropped.
+pub fn project<T: AsRef<[u8]>>(files: &[(&str, T)]) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (file, contents) in files {
+        let path = dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
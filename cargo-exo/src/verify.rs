@@ -0,0 +1,228 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cargo::CargoCommand, diagnostic::Diagnostic, error::ExoError, source::DiagnosticSource,
+};
+use cargo_exo_cli::Cli;
+use colored::Colorize;
+use itertools::Itertools;
+
+/// The contents of files before we changed them, so the changes can be rolled back.
+#[derive(Default)]
+pub struct Snapshot {
+    files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl Snapshot {
+    pub fn take<P: AsRef<Path>>(files: impl IntoIterator<Item = P>, project_root: &Path) -> Self {
+        let files = files
+            .into_iter()
+            .map(|file| project_root.join(file))
+            .unique()
+            // Anything we can't read won't be updated either
+            .filter_map(|path| fs::read(&path).ok().map(|contents| (path, contents)))
+            .collect();
+
+        Self { files }
+    }
+
+    pub fn has_changes(&self) -> bool {
+        self.files
+            .iter()
+            .any(|(path, contents)| fs::read(path).ok().as_ref() != Some(contents))
+    }
+
+    /// Each file that's different now, with what it looked like before and after.
+    pub fn changes(&self) -> Vec<(PathBuf, Vec<u8>, Vec<u8>)> {
+        self.files
+            .iter()
+            .filter_map(|(path, before)| {
+                let after = fs::read(path).ok()?;
+                (after != *before).then(|| (path.clone(), before.clone(), after))
+            })
+            .collect()
+    }
+
+    pub fn restore(&self) -> Result<(), ExoError> {
+        for (path, contents) in &self.files {
+            fs::write(path, contents)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What re-running the command after a fix found.
+#[derive(Default)]
+pub struct Verified {
+    /// The diagnostics fixed by the changes we kept, or every one they were meant to fix if the
+    /// command was stopped before we could tell
+    pub fixed: Vec<String>,
+    /// Whether the command succeeded with the changes we kept
+    pub success: bool,
+}
+
+/// Re-runs the command after fixes have been applied and rolls them back if they made things worse.
+pub fn verify<C: Cli>(
+    command: CargoCommand,
+    source: &dyn DiagnosticSource,
+    before: &[Diagnostic],
+    snapshot: &Snapshot,
+    ignore: &[String],
+    exclude: &[String],
+) -> Result<Verified, ExoError> {
+    if !snapshot.has_changes() {
+        return Ok(Verified::default());
+    }
+
+    println!();
+    println!("🤖 checking the changes ...");
+
+    let after = source.prepare(command).run(false, false);
+
+    let before = headlines(before, ignore, exclude);
+
+    // Without the whole output it'd look like everything was fixed. The changes are kept, and
+    // recorded against what they were meant to fix so they can still be undone
+    if let Some(reason) = after.interruption() {
+        println!(
+            "🤖 couldn't check the changes, cargo {}, keeping them unchecked",
+            reason
+        );
+        return Ok(Verified {
+            fixed: before,
+            success: false,
+        });
+    }
+
+    let success = after.was_success();
+
+    let after = headlines(&source.diagnostics(&after), ignore, exclude);
+
+    let fixed = difference(&before, &after);
+    let introduced = difference(&after, &before);
+
+    println!(
+        "🤖 {} diagnostics before, {} after",
+        before.len(),
+        after.len()
+    );
+    for diagnostic in &fixed {
+        println!("{}", format!("- {}", diagnostic).green());
+    }
+    for diagnostic in &introduced {
+        println!("{}", format!("+ {}", diagnostic).red());
+    }
+
+    let new_errors = introduced
+        .iter()
+        .filter(|diagnostic| diagnostic.starts_with("error"))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if !new_errors.is_empty() && C::confirm_revert(&new_errors) {
+        println!("🤖 reverting changes");
+        snapshot.restore()?;
+        return Ok(Verified::default());
+    }
+
+    Ok(Verified { fixed, success })
+}
+
+/// A one line summary of each diagnostic the config doesn't tell us to ignore. Line numbers are
+/// left out because they'll have moved after applying a fix.
+fn headlines(diagnostics: &[Diagnostic], ignore: &[String], exclude: &[String]) -> Vec<String> {
+    diagnostics
+        .iter()
+        .filter(|diagnostic| !diagnostic.is_summary())
+        .filter(|diagnostic| !diagnostic.is_ignored(ignore, exclude))
+        .map(Diagnostic::headline)
+        .collect()
+}
+
+/// Everything in `a` that isn't in `b`, counting duplicates.
+fn difference(a: &[String], b: &[String]) -> Vec<String> {
+    let mut remaining = b.to_vec();
+
+    a.iter()
+        .filter(
+            |diagnostic| match remaining.iter().position(|other| other == *diagnostic) {
+                Some(index) => {
+                    remaining.remove(index);
+                    false
+                }
+                None => true,
+            },
+        )
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostic::Level;
+
+    use super::*;
+
+    #[test]
+    fn it_counts_duplicates_when_diffing_diagnostics() {
+        let before = vec![
+            "warning: unused variable: `x`".to_string(),
+            "warning: unused variable: `x`".to_string(),
+            "error[E0425]: cannot find value `y` in this scope".to_string(),
+        ];
+        let after = vec![
+            "warning: unused variable: `x`".to_string(),
+            "error[E0308]: mismatched types".to_string(),
+        ];
+
+        assert_eq!(
+            difference(&before, &after),
+            vec![
+                "warning: unused variable: `x`".to_string(),
+                "error[E0425]: cannot find value `y` in this scope".to_string(),
+            ]
+        );
+        assert_eq!(
+            difference(&after, &before),
+            vec!["error[E0308]: mismatched types".to_string()]
+        );
+    }
+
+    #[test]
+    fn it_leaves_ignored_diagnostics_out_of_the_comparison() {
+        let diagnostic = |code: &str, file: &str| {
+            Diagnostic::from_text(
+                Level::Error,
+                Some(code),
+                "something's wrong",
+                Some((file, 1, 1)),
+                None,
+            )
+        };
+        let diagnostics = vec![
+            diagnostic("clippy::needless_return", "src/main.rs"),
+            diagnostic("E0308", "src/generated.rs"),
+            diagnostic("E0425", "src/main.rs"),
+            Diagnostic::from_text(
+                Level::Error,
+                None,
+                "aborting due to 2 previous errors",
+                None,
+                None,
+            ),
+        ];
+
+        assert_eq!(
+            headlines(
+                &diagnostics,
+                &["clippy::needless_return".to_string()],
+                &["src/generated.rs".to_string()]
+            ),
+            vec!["error[E0425]: something's wrong".to_string()]
+        );
+    }
+}
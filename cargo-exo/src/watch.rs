@@ -0,0 +1,146 @@
+use std::{
+    convert::Infallible,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use ignore_files::IgnoreFilter;
+use tokio::{sync::mpsc, task::JoinHandle};
+use watchexec::{
+    action::{Action, Outcome},
+    config::{InitConfig, RuntimeConfig},
+    error::{CriticalError, RuntimeError},
+    filter::Filterer,
+    handler::PrintDebug,
+    Watchexec,
+};
+use watchexec_events::{Event, FileType, Priority};
+use watchexec_signals::Signal;
+
+/// Watches the workspace for changes, ignoring anything covered by a `.gitignore` and the `target/`
+/// directory. Events are dropped while paused so our own edits don't trigger another run.
+pub struct Watcher {
+    // Dropping this stops the workers, so it needs to live as long as we do
+    _watchexec: Arc<Watchexec>,
+    paused: Arc<AtomicBool>,
+    changes: mpsc::Receiver<()>,
+    main: JoinHandle<Result<(), CriticalError>>,
+}
+
+impl Watcher {
+    pub async fn new(project_root: &Path) -> Self {
+        let paused = Arc::new(AtomicBool::new(false));
+        let (changes_tx, changes) = mpsc::channel(1);
+
+        let mut init = InitConfig::default();
+        init.on_error(PrintDebug(std::io::stderr()));
+
+        let mut runtime = RuntimeConfig::default();
+        runtime.pathset([project_root]);
+        runtime.filterer(Arc::new(
+            WorkspaceFilter::new(project_root, paused.clone()).await,
+        ));
+        let stop_paused = paused.clone();
+        runtime.on_action(move |action: Action| {
+            let changes_tx = changes_tx.clone();
+            let paused = stop_paused.clone();
+            async move {
+                let stop = action
+                    .events
+                    .iter()
+                    .flat_map(Event::signals)
+                    .any(|signal| matches!(signal, Signal::Interrupt | Signal::Terminate));
+
+                if stop {
+                    // In the middle of a run main's Ctrl-C handling stops it and keeps what's been
+                    // fixed, the loop ends once it's done
+                    if !paused.load(Ordering::SeqCst) {
+                        action.outcome(Outcome::Exit);
+                    }
+                } else {
+                    // The channel only holds one pending change, anything else is already covered by it
+                    let _ = changes_tx.try_send(());
+                }
+
+                Ok::<(), Infallible>(())
+            }
+        });
+
+        let watchexec =
+            Watchexec::new(init, runtime.clone()).expect("Failed to start file watcher");
+        watchexec
+            .reconfigure(runtime)
+            .expect("Failed to configure file watcher");
+
+        Self {
+            main: watchexec.main(),
+            _watchexec: watchexec,
+            paused,
+            changes,
+        }
+    }
+
+    /// Stop reacting to file events, e.g. while we're asking the user about a change.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Waits for the next change in the workspace. Returns `false` when the watcher has stopped.
+    pub async fn changed(&mut self) -> bool {
+        tokio::select! {
+            change = self.changes.recv() => change.is_some(),
+            _ = &mut self.main => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct WorkspaceFilter {
+    paused: Arc<AtomicBool>,
+    ignores: IgnoreFilter,
+    target_dir: PathBuf,
+}
+
+impl WorkspaceFilter {
+    async fn new(project_root: &Path, paused: Arc<AtomicBool>) -> Self {
+        let (files, _errors) = ignore_files::from_origin(project_root).await;
+
+        let mut ignores = IgnoreFilter::new(project_root, &files)
+            .await
+            .unwrap_or_else(|_| IgnoreFilter::empty(project_root));
+        let _ = ignores.add_globs(&[".git/"], Some(&project_root.to_path_buf()));
+
+        Self {
+            paused,
+            ignores,
+            target_dir: project_root.join("target"),
+        }
+    }
+}
+
+impl Filterer for WorkspaceFilter {
+    fn check_event(&self, event: &Event, _priority: Priority) -> Result<bool, RuntimeError> {
+        // Always let signals through so Ctrl-C can stop the watcher
+        if event.signals().next().is_some() {
+            return Ok(true);
+        }
+
+        if self.paused.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+
+        Ok(event.paths().any(|(path, file_type)| {
+            let is_dir = matches!(file_type, Some(FileType::Dir));
+
+            !path.starts_with(&self.target_dir)
+                && !self.ignores.match_path(path, is_dir).is_ignore()
+        }))
+    }
+}